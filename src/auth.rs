@@ -1,8 +1,19 @@
+use super::chain::{self, Chain};
+use super::config::Config;
+use super::cookies;
+use super::error::{ApiError, ApiResult};
+use super::http::ApiRequest;
+use super::store::Store;
+use chrono::{DateTime, Utc};
 use ethers::types::{Signature, H160};
+use ethers::utils::hash_message;
+use rand::distributions::Alphanumeric;
 use rand::Rng;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use siwe::Message;
+use std::convert::TryFrom;
 use std::str::FromStr;
 use worker::*;
 
@@ -11,7 +22,7 @@ use worker::*;
 /// b) A signature of said message
 ///
 /// EIP4361 Template:
-/// ```
+/// ```text
 /// ${domain} wants you to sign in with your Ethereum account:
 /// ${address}
 ///
@@ -33,7 +44,7 @@ use worker::*;
 /// ```
 /// Source: [EIP4361](https://eips.ethereum.org/EIPS/eip-4361)
 ///
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct AuthRequest {
     message: String,
     signature: String,
@@ -41,124 +52,257 @@ pub struct AuthRequest {
 
 /// An authorization is issued to a particular address based on the fields included in the
 /// AuthRequest message. With the Resources vecotr, the API can have even more granular control
-/// over the access control of a particular address.
+/// over the access control of a particular address: a token with resources can only access the
+/// URLs under them.
 /// All the fields are populated by a AuthRequest.message, from the fields with the same name.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 pub struct Authorization {
     resources: Vec<String>,
     issued_at: String,
     expiration_time: Option<String>,
     not_before: Option<String>,
+    #[schemars(with = "String")]
     pub address: H160,
+    /// The chain that the message was issued for. The funding details of a workstream can only
+    /// be changed with a session on the chain of the workstream.
+    #[serde(default = "chain::mainnet")]
+    pub chain_id: u64,
 }
 
 impl Authorization {
-    /// Parses a worker::Request for an authentication token, serialized as a JSON object in the
-    /// body of the request. The authentication token is used to
-    /// retrieve the related Authorization and verify that the token-holder can access the
-    /// particular resource.
-    pub async fn parse_request(req: &Request) -> Result<String> {
-        let headers = req.headers();
-        let bearer = headers.get("BEARER")?;
-        let cookie = headers.get("AUTH-SIWE")?;
+    /// Parses an ApiRequest for an authentication token, passed either in the standard
+    /// `Authorization: Bearer <token>` header or in the `SIWE-AUTH` cookie that is set by
+    /// `/api/v1/authorize`. The header takes precedence over the cookie.
+    /// The authentication token is used to retrieve the related Authorization and verify that the
+    /// token-holder can access the particular resource.
+    pub fn parse_request(req: &ApiRequest) -> Result<String> {
+        let bearer = req.header("Authorization").and_then(|header| {
+            let (scheme, token) = header.trim().split_once(' ')?;
+            if scheme.eq_ignore_ascii_case("bearer") {
+                Some(token.trim().to_owned())
+            } else {
+                None
+            }
+        });
+        let cookie = req
+            .header("Cookie")
+            .and_then(|header| cookies::parse(header).remove(cookies::SESSION_COOKIE));
         match bearer.or(cookie) {
-            Some(token) => Ok(token),
-            None => Err(worker::Error::from("no authorization header found")),
+            Some(token) if !token.is_empty() => Ok(token),
+            _ => Err(worker::Error::from("no authorization token found")),
         }
     }
-    /// Get an authorizsation from the Cloudflare KV store, based on a token. The token is retrived
+    /// Get an authorizsation from the store, based on a token. The token is retrived
     /// from the request with parse_request and used as the key to find the Authorization struct.
-    pub async fn get<T>(env: &Env, token: T) -> Result<Option<Authorization>>
+    pub async fn get<T>(store: &dyn Store, token: T) -> Result<Option<Authorization>>
     where
         T: Into<String>,
     {
-        let store = env.kv("AUTHENTICATION")?;
-        store
-            .get(&token.into())
-            .json::<Authorization>()
-            .await
-            .map_err(worker::Error::from)
+        store.get_authorization(&token.into()).await
     }
-    /// Creates an Authorization in the Cloudflare KC store based on an AuthRequest.
+    /// Checks if the authorization can be used at `now`, based on the `not_before` and
+    /// `expiration_time` of the message that it was created from. The store is expected to drop
+    /// expired authorizations on its own, but Cloudflare KV is eventually consistent, so an
+    /// expired authorization can still be returned for a while.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let not_before = self.not_before.as_deref().map(parse_timestamp);
+        let expiration_time = self.expiration_time.as_deref().map(parse_timestamp);
+        match (not_before, expiration_time) {
+            (Some(None), _) | (_, Some(None)) => false,
+            (Some(Some(not_before)), _) if now < not_before => false,
+            (_, Some(Some(expiration_time))) if now >= expiration_time => false,
+            _ => true,
+        }
+    }
+    /// Returns the time at which the authorization expires, if the message that it was created
+    /// from has an `expiration_time`.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expiration_time.as_deref().and_then(parse_timestamp)
+    }
+    /// Checks if the authorization grants access to `url`. An authorization without `resources`
+    /// grants access to every URL. Otherwise, `url` must be one of the resources, or be nested
+    /// under one of them. For example, the resource
+    /// `https://example.com/api/v1/users/A/workstreams/W` grants access to
+    /// `https://example.com/api/v1/users/A/workstreams/W/applications`, but not to
+    /// `https://example.com/api/v1/users/A/workstreams`.
+    pub fn grants(&self, url: &Url) -> bool {
+        if self.resources.is_empty() {
+            return true;
+        }
+        self.resources.iter().any(|resource| {
+            let resource = match Url::parse(resource) {
+                Ok(resource) => resource,
+                Err(_) => return false,
+            };
+            if resource.origin() != url.origin() {
+                return false;
+            }
+            let prefix = resource.path().trim_end_matches('/');
+            let path = url.path();
+            path == prefix || path.starts_with(&format!("{}/", prefix))
+        })
+    }
+    /// Creates an Authorization in the store based on an AuthRequest.
     /// After the message is verified against the signature, the authorization is tied to the
     /// address that signed the message.  The message is converted to bytes and hashed with a
     /// pseudorandomly generated salt. The hash is used as the KEY of the Authorization and
-    /// returned to the user to be used as a token.
+    /// returned to the user to be used as a token, together with the Authorization.
     ///
-    /// For better UX, we return the token in the form of a cookie that can be used by the web
-    /// application.
+    /// For better UX, the route returns the token in the form of a cookie that can be used by the
+    /// web application and that expires together with the Authorization.
     ///
-    /// The Authorization value is set to expire at the Cloduflare KV store at the same time that
+    /// The Authorization value is set to expire at the store at the same time that
     /// it expires as an Authorization, defined in the `expiration_time` field of the
     /// SIWE::Message. That way, we don't have to deal with stale records, but Cloudflare takes
     /// care of it. After it expires, the token will no longer be usable and the user will have to
     /// Authorize again and use a new token.
     ///
-    pub async fn create(env: &Env, auth: AuthRequest) -> Result<String> {
-        let message: Message =
-            Message::from_str(&auth.message).map_err(|err| worker::Error::from(err.to_string()))?;
-        match message.verify(
-            Signature::from_str(&auth.signature)
-                .map_err(|err| worker::Error::from(err.to_string()))?
-                .into(),
-        ) {
-            Ok(_) => {
-                let authentication = env.kv("AUTHENTICATION")?;
-                let mut rng = rand::thread_rng();
-                let mut hasher = Sha256::new();
-                let message: Message = Message::from_str(&auth.message)
-                    .map_err(|err| worker::Error::from(err.to_string()))?;
-                let auth = Authorization {
-                    resources: message
-                        .resources
-                        .iter()
-                        .map(|x| x.as_str().to_owned())
-                        .collect::<Vec<String>>(),
-                    issued_at: format!("{}", message.issued_at),
-                    expiration_time: message.expiration_time.clone().map(|x| format!("{}", x)),
-                    not_before: message.not_before.map(|x| format!("{}", x)),
-                    address: H160(message.address),
-                };
-                let auth_string: String = serde_json::to_string(&auth).unwrap();
-                hasher.update(auth_string.as_bytes());
-                // add salt to the auth token
-                hasher.update(rng.gen::<[u8; 32]>());
-                let hash = format!("{:X}", hasher.finalize());
-                authentication
-                    .put(&hash, &auth_string)?
-                    .expiration(
-                        message
-                            .expiration_time
-                            .unwrap()
-                            .as_ref()
-                            .timestamp()
-                            .unsigned_abs(),
-                    )
-                    .execute()
-                    .await?;
-                Ok(hash)
-            }
-            Err(_) => Err(worker::Error::from(
-                "Failed to verify supplied message with signature",
-            )),
+    /// To protect against replay attacks, the message must be issued for the `domain` and `uri`
+    /// of the API's Config, and for one of its `chain_ids`, and its `nonce` must have been issued
+    /// by the API with `issue_nonce()`. Every nonce can be used only once. The authorization
+    /// remembers the chain of the message.
+    ///
+    /// Smart contract wallets can't sign with ECDSA, so their signatures are verified on-chain,
+    /// by calling `isValidSignature` (EIP1271) on the address of the message through `chain`.
+    ///
+    /// A message, signature or nonce that doesn't check out fails with `ApiError::Unauthorized`.
+    /// A failure of the store is an internal error, and one of the chain an upstream error.
+    pub async fn create(
+        store: &dyn Store,
+        chain: &dyn Chain,
+        config: &Config,
+        auth: AuthRequest,
+    ) -> ApiResult<(String, Authorization)> {
+        let message: Message = Message::from_str(&auth.message)
+            .map_err(|err| ApiError::Unauthorized(err.to_string()))?;
+        let signature =
+            hex::decode(&auth.signature).map_err(|err| ApiError::Unauthorized(err.to_string()))?;
+        check_message(&message, config)?;
+        if !verify_signature(chain, &message, &auth.message, &signature)
+            .await
+            .map_err(ApiError::upstream)?
+        {
+            return Err(ApiError::Unauthorized(
+                "Failed to verify supplied message with signature".into(),
+            ));
+        }
+        let mut rng = rand::thread_rng();
+        let mut hasher = Sha256::new();
+        if !store.take_nonce(&message.nonce).await? {
+            return Err(ApiError::Unauthorized(
+                "Nonce is invalid or has already been used".into(),
+            ));
+        }
+        let auth = Authorization {
+            resources: message
+                .resources
+                .iter()
+                .map(|x| x.as_str().to_owned())
+                .collect::<Vec<String>>(),
+            issued_at: format!("{}", message.issued_at),
+            expiration_time: message.expiration_time.clone().map(|x| format!("{}", x)),
+            not_before: message.not_before.map(|x| format!("{}", x)),
+            address: H160(message.address),
+            chain_id: message.chain_id,
+        };
+        if matches!(auth.expires_at(), Some(expiration) if expiration <= Utc::now()) {
+            return Err(ApiError::Unauthorized("Message has expired".into()));
         }
+        let auth_string: String = serde_json::to_string(&auth).unwrap();
+        hasher.update(auth_string.as_bytes());
+        // add salt to the auth token
+        hasher.update(rng.gen::<[u8; 32]>());
+        let hash = format!("{:X}", hasher.finalize());
+        store
+            .put_authorization(
+                &hash,
+                &auth,
+                message
+                    .expiration_time
+                    .map(|x| x.as_ref().timestamp().unsigned_abs()),
+            )
+            .await?;
+        Ok((hash, auth))
+    }
+}
+
+/// Verifies that the EIP4361 `message` was signed by its address. Externally owned accounts sign
+/// with ECDSA, which is verified locally. If that fails, the address may be a smart contract
+/// wallet, like a Gnosis Safe, so the signature of the EIP191 hash of the message (`raw`, as it
+/// was signed) is verified by the wallet itself, with EIP1271.
+async fn verify_signature(
+    chain: &dyn Chain,
+    message: &Message,
+    raw: &str,
+    signature: &[u8],
+) -> Result<bool> {
+    if let Ok(ecdsa) = Signature::try_from(signature) {
+        if message.verify(ecdsa.into()).is_ok() {
+            return Ok(true);
+        }
+    }
+    chain
+        .is_valid_signature(
+            message.chain_id,
+            H160(message.address),
+            hash_message(raw),
+            signature,
+        )
+        .await
+}
+
+/// Parses a timestamp of an Authorization. The timestamps are stored in the RFC 3339 format in
+/// which they appear in EIP4361 messages.
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+/// Checks that an EIP4361 message was issued for this API and not for some other website that
+/// the user signed in to.
+fn check_message(message: &Message, config: &Config) -> ApiResult<()> {
+    if message.domain.to_string() != config.domain {
+        return Err(ApiError::Unauthorized(
+            "Message was issued for another domain".into(),
+        ));
+    }
+    if message.uri.as_str() != config.uri {
+        return Err(ApiError::Unauthorized(
+            "Message was issued for another URI".into(),
+        ));
+    }
+    if !config.chain_ids.contains(&message.chain_id) {
+        return Err(ApiError::Unauthorized(
+            "Message was issued for an unsupported chain".into(),
+        ));
     }
+    Ok(())
 }
+
+/// Issues a single-use nonce that must be included in the EIP4361 message of the next
+/// AuthRequest. The nonce expires after `Config.nonce_ttl` seconds.
+pub async fn issue_nonce(store: &dyn Store, config: &Config) -> Result<String> {
+    let nonce: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(17)
+        .map(char::from)
+        .collect();
+    store.put_nonce(&nonce, config.nonce_ttl).await?;
+    Ok(nonce)
+}
+
 impl AuthRequest {
-    /// Parses a worker::Request struct for an AuthRequest struct, serialized as a JSON object in
+    /// Parses an ApiRequest struct for an AuthRequest struct, serialized as a JSON object in
     /// the body of the request.
     ///
-    /// ```no_run
-    /// let router = Router::new();
-    /// router.post_async("/api/v1/authorize", |req, ctx| async move {
-    /// let auth_req: AuthRequest = AuthRequest::from_req(req).await?;
-    /// }).run(req, ctx).await
+    /// ```ignore
+    /// async fn authorize(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    ///     let auth_req: AuthRequest = AuthRequest::from_req(&req)?;
+    /// }
     /// ```
-    pub async fn from_req(mut req: Request) -> Result<AuthRequest> {
-        let body = req
-            .json::<AuthRequest>()
-            .await
-            .map_err(|error| worker::Error::from(format!("body parsing: {:?}", error)))?;
+    pub fn from_req(req: &ApiRequest) -> ApiResult<AuthRequest> {
+        let body = req.json::<AuthRequest>()?;
         let sig: String = body.signature.trim_start_matches("0x").to_owned();
         let msg: String = body.message;
         Ok(AuthRequest {
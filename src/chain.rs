@@ -0,0 +1,519 @@
+use super::indexer::MAX_BLOCKS;
+use super::utils::log;
+use async_trait::async_trait;
+use ethers::abi::{parse_abi, Detokenize, Tokenize};
+use ethers::contract::BaseContract;
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Filter, Log, TransactionRequest, ValueOrArray, H256, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use worker::{Error, Result};
+
+/// The subset of the DripsHub ABI that the API uses, in human-readable form.
+const DRIPS_HUB_ABI: &[&str] = &[
+    "function dripsHash(address user, uint256 account) view returns (bytes32)",
+    "function hashDrips(uint64 updateTime, uint128 balance, (address receiver, uint128 amtPerSec)[] receivers) pure returns (bytes32)",
+    "event DripsUpdated(address indexed user, uint256 indexed account, uint128 balance, (address receiver, uint128 amtPerSec)[] receivers)",
+    "event Given(address indexed user, uint256 indexed account, address indexed receiver, uint128 amt)",
+    "event Collected(address indexed user, uint128 collected, uint128 split)",
+];
+
+/// The events of the DripsHub that the indexer follows (see `indexer`).
+const DRIPS_EVENTS: &[&str] = &["DripsUpdated", "Given", "Collected"];
+
+/// The interface of smart contract wallets that can sign messages, as defined by
+/// [EIP1271](https://eips.ethereum.org/EIPS/eip-1271).
+const EIP1271_ABI: &[&str] =
+    &["function isValidSignature(bytes32 hash, bytes signature) view returns (bytes4)"];
+
+/// The value that `isValidSignature` returns when the signature is valid.
+const EIP1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// The id of Ethereum mainnet. The workstreams and the sessions that were created before the API
+/// supported other chains are on mainnet.
+pub const MAINNET: u64 = 1;
+
+/// The default `chain_id` of the workstreams and the sessions, for serde.
+pub fn mainnet() -> u64 {
+    MAINNET
+}
+
+/// The configuration of a drips account, as it was set on-chain by the last `DripsUpdated`
+/// event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DripsState {
+    /// UNIX timestamp (in seconds) of the block in which the drips were last updated.
+    pub update_time: u64,
+    /// The balance of the account at `update_time`.
+    pub balance: U256,
+    /// The receivers of the account with their amount per second.
+    pub receivers: Vec<(Address, U256)>,
+}
+
+impl DripsState {
+    /// The amount that the account streams to all its receivers per second.
+    pub fn amount_per_second(&self) -> U256 {
+        self.receivers
+            .iter()
+            .fold(U256::zero(), |total, (_, amount)| {
+                total.saturating_add(*amount)
+            })
+    }
+
+    /// The balance that is left in the account at `timestamp`, after streaming to the receivers
+    /// since `update_time`.
+    pub fn balance_at(&self, timestamp: u64) -> U256 {
+        let elapsed = U256::from(timestamp.saturating_sub(self.update_time));
+        self.balance
+            .saturating_sub(self.amount_per_second().saturating_mul(elapsed))
+    }
+}
+
+/// An event of a DripsHub, as it was emitted on-chain.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DripsEvent {
+    pub block_number: u64,
+    /// The position of the log in its block, which identifies the event together with
+    /// `block_number`.
+    pub log_index: u64,
+    pub transaction_hash: H256,
+    /// UNIX timestamp (in seconds) of the block.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: DripsEventKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "event")]
+pub enum DripsEventKind {
+    /// `account` of `user` was configured to stream to `receivers` (with their amount per
+    /// second), with `balance` left in the account.
+    DripsUpdated {
+        user: Address,
+        account: U256,
+        balance: U256,
+        receivers: Vec<(Address, U256)>,
+    },
+    /// `account` of `user` gave `amount` to `receiver` at once.
+    Given {
+        user: Address,
+        account: U256,
+        receiver: Address,
+        amount: U256,
+    },
+    /// `user` collected the funds that were streamed or given to it, from all its senders.
+    /// `split` is the part that was split to the receivers of `user`.
+    Collected {
+        user: Address,
+        collected: U256,
+        split: U256,
+    },
+}
+
+/// The ABI of the DripsHub contract, which decodes its calls and events.
+pub fn drips_hub_abi() -> Result<BaseContract> {
+    Ok(BaseContract::from(
+        parse_abi(DRIPS_HUB_ABI).map_err(rpc_error)?,
+    ))
+}
+
+/// Decodes a log of a DripsHub that was emitted in a block with `timestamp`. The logs of the
+/// events that the indexer doesn't follow are `None`.
+pub fn decode_drips_log(
+    drips_hub: &BaseContract,
+    log: Log,
+    timestamp: u64,
+) -> Result<Option<DripsEvent>> {
+    let signature = |name: &str| {
+        drips_hub
+            .abi()
+            .event(name)
+            .map(|event| event.signature())
+            .map_err(rpc_error)
+    };
+    let topic = match log.topics.first() {
+        Some(topic) => *topic,
+        None => return Ok(None),
+    };
+    let (block_number, log_index) = match (log.block_number, log.log_index) {
+        (Some(block_number), Some(log_index)) => (block_number.as_u64(), log_index.as_u64()),
+        _ => return Err(Error::from("rpc: DripsHub log is pending")),
+    };
+    let transaction_hash = log.transaction_hash.unwrap_or_default();
+    let kind = if topic == signature("DripsUpdated")? {
+        let (user, account, balance, receivers): (Address, U256, u128, Vec<(Address, u128)>) =
+            drips_hub
+                .decode_event("DripsUpdated", log.topics, log.data)
+                .map_err(rpc_error)?;
+        DripsEventKind::DripsUpdated {
+            user,
+            account,
+            balance: U256::from(balance),
+            receivers: receivers
+                .into_iter()
+                .map(|(receiver, amount)| (receiver, U256::from(amount)))
+                .collect(),
+        }
+    } else if topic == signature("Given")? {
+        let (user, account, receiver, amount): (Address, U256, Address, u128) = drips_hub
+            .decode_event("Given", log.topics, log.data)
+            .map_err(rpc_error)?;
+        DripsEventKind::Given {
+            user,
+            account,
+            receiver,
+            amount: U256::from(amount),
+        }
+    } else if topic == signature("Collected")? {
+        let (user, collected, split): (Address, u128, u128) = drips_hub
+            .decode_event("Collected", log.topics, log.data)
+            .map_err(rpc_error)?;
+        DripsEventKind::Collected {
+            user,
+            collected: U256::from(collected),
+            split: U256::from(split),
+        }
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(DripsEvent {
+        block_number,
+        log_index,
+        transaction_hash,
+        timestamp,
+        kind,
+    }))
+}
+
+/// Read access to the blockchains that the API runs on, identified by their chain id. The API
+/// never talks to an Ethereum node directly, so that the verification of the on-chain state can
+/// be exercised natively against a test double.
+#[async_trait(?Send)]
+pub trait Chain {
+    /// Returns the current drips configuration of `account` of `user` in `drips_hub`, on
+    /// `chain_id`, or `None` if the account has never been configured. The events of the
+    /// DripsHub are read from `start_block`, the block that it was deployed in (see `Currency`).
+    async fn drips_state(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        start_block: u64,
+        user: Address,
+        account: U256,
+    ) -> Result<Option<DripsState>>;
+
+    /// Checks if `signature` is a valid signature of `hash` for the smart contract wallet at
+    /// `wallet` on `chain_id`, according to EIP1271. Returns `false` if `wallet` is not a contract
+    /// or doesn't implement EIP1271.
+    async fn is_valid_signature(
+        &self,
+        chain_id: u64,
+        wallet: Address,
+        hash: H256,
+        signature: &[u8],
+    ) -> Result<bool>;
+
+    /// Returns the number of the latest block of `chain_id`.
+    async fn block_number(&self, chain_id: u64) -> Result<u64>;
+
+    /// Returns the events of `drips_hub` (see `DripsEventKind`) that were emitted on `chain_id`
+    /// between the blocks `from` and `to`, both included, in the order of the chain.
+    async fn drips_events(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<DripsEvent>>;
+
+    /// Returns the total amount that `user` has collected from `drips_hub` on `chain_id`, from
+    /// all of its senders, according to its `Collected` events since `start_block`, the block
+    /// that the DripsHub was deployed in.
+    async fn collected(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        start_block: u64,
+        user: Address,
+    ) -> Result<U256>;
+}
+
+/// A Chain that queries an Ethereum node per chain over JSON-RPC. The URLs of the nodes are
+/// configured with the `RPC_URL` environment variables of the worker (see `Config::rpc_urls`).
+pub struct RpcChain {
+    providers: HashMap<u64, Provider<Http>>,
+    drips_hub: BaseContract,
+    eip1271: BaseContract,
+}
+
+fn rpc_error<E: ToString>(err: E) -> Error {
+    Error::from(format!("rpc: {}", err.to_string()))
+}
+
+impl RpcChain {
+    /// Creates a Chain that queries the nodes of `urls`, which maps every chain id to the URL of
+    /// its node.
+    pub fn new(urls: &HashMap<u64, String>) -> Result<RpcChain> {
+        let mut providers = HashMap::new();
+        for (chain_id, url) in urls {
+            providers.insert(
+                *chain_id,
+                Provider::<Http>::try_from(url.as_str()).map_err(rpc_error)?,
+            );
+        }
+        Ok(RpcChain {
+            providers,
+            drips_hub: drips_hub_abi()?,
+            eip1271: BaseContract::from(parse_abi(EIP1271_ABI).map_err(rpc_error)?),
+        })
+    }
+
+    fn provider(&self, chain_id: u64) -> Result<&Provider<Http>> {
+        self.providers
+            .get(&chain_id)
+            .ok_or_else(|| Error::from(format!("rpc: no node for chain {}", chain_id)))
+    }
+
+    async fn call<T: Detokenize, A: Tokenize>(
+        &self,
+        chain_id: u64,
+        abi: &BaseContract,
+        contract: Address,
+        function: &str,
+        args: A,
+    ) -> Result<T> {
+        let data = abi.encode(function, args).map_err(rpc_error)?;
+        let tx: TypedTransaction = TransactionRequest::new().to(contract).data(data).into();
+        let output = self
+            .provider(chain_id)?
+            .call(&tx, None)
+            .await
+            .map_err(rpc_error)?;
+        abi.decode_output(function, output).map_err(rpc_error)
+    }
+}
+
+#[async_trait(?Send)]
+impl Chain for RpcChain {
+    /// The account of a user that has never been configured has no drips hash, so its events
+    /// are only looked up otherwise, from the latest block backwards, `MAX_BLOCKS` blocks per
+    /// request.
+    async fn drips_state(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        start_block: u64,
+        user: Address,
+        account: U256,
+    ) -> Result<Option<DripsState>> {
+        let provider = self.provider(chain_id)?;
+        let current: H256 = self
+            .call(
+                chain_id,
+                &self.drips_hub,
+                drips_hub,
+                "dripsHash",
+                (user, account),
+            )
+            .await?;
+        if current.is_zero() {
+            return Ok(None);
+        }
+        let event = self
+            .drips_hub
+            .abi()
+            .event("DripsUpdated")
+            .map_err(rpc_error)?;
+        let mut account_topic = [0u8; 32];
+        account.to_big_endian(&mut account_topic);
+        let filter = Filter::new()
+            .address(drips_hub)
+            .topic0(event.signature())
+            .topic1(H256::from(user))
+            .topic2(H256::from(account_topic));
+        let mut to = self.block_number(chain_id).await?;
+        let log = loop {
+            let from = to.saturating_sub(MAX_BLOCKS - 1).max(start_block);
+            let logs = provider
+                .get_logs(&filter.clone().from_block(from).to_block(to))
+                .await
+                .map_err(rpc_error)?;
+            if let Some(log) = logs.into_iter().last() {
+                break log;
+            }
+            if from <= start_block {
+                return Err(Error::from(
+                    "rpc: the drips account has a drips hash, but no DripsUpdated event",
+                ));
+            }
+            to = from - 1;
+        };
+        let (_, _, balance, receivers): (Address, U256, u128, Vec<(Address, u128)>) = self
+            .drips_hub
+            .decode_event("DripsUpdated", log.topics, log.data)
+            .map_err(rpc_error)?;
+        let block_number = log
+            .block_number
+            .ok_or_else(|| Error::from("rpc: DripsUpdated log is pending"))?;
+        let block = provider
+            .get_block(block_number)
+            .await
+            .map_err(rpc_error)?
+            .ok_or_else(|| Error::from("rpc: unknown block"))?;
+        let update_time = block.timestamp.as_u64();
+        // make sure that the event describes the current configuration of the account
+        let expected: H256 = self
+            .call(
+                chain_id,
+                &self.drips_hub,
+                drips_hub,
+                "hashDrips",
+                (update_time, balance, receivers.clone()),
+            )
+            .await?;
+        if current != expected {
+            return Err(Error::from(
+                "rpc: the last DripsUpdated event doesn't match the drips hash of the account",
+            ));
+        }
+        Ok(Some(DripsState {
+            update_time,
+            balance: U256::from(balance),
+            receivers: receivers
+                .into_iter()
+                .map(|(receiver, amount)| (receiver, U256::from(amount)))
+                .collect(),
+        }))
+    }
+
+    async fn is_valid_signature(
+        &self,
+        chain_id: u64,
+        wallet: Address,
+        hash: H256,
+        signature: &[u8],
+    ) -> Result<bool> {
+        let code = self
+            .provider(chain_id)?
+            .get_code(wallet, None)
+            .await
+            .map_err(rpc_error)?;
+        if code.as_ref().is_empty() {
+            return Ok(false);
+        }
+        // wallets that don't implement EIP1271 revert, which is just an invalid signature
+        let result: Result<[u8; 4]> = self
+            .call(
+                chain_id,
+                &self.eip1271,
+                wallet,
+                "isValidSignature",
+                (hash, Bytes::from(signature.to_vec())),
+            )
+            .await;
+        match result {
+            Ok(value) => Ok(value == EIP1271_MAGIC_VALUE),
+            Err(err) => {
+                log(&format!("isValidSignature of {:?} failed: {}", wallet, err));
+                Ok(false)
+            }
+        }
+    }
+
+    async fn block_number(&self, chain_id: u64) -> Result<u64> {
+        let block_number = self
+            .provider(chain_id)?
+            .get_block_number()
+            .await
+            .map_err(rpc_error)?;
+        Ok(block_number.as_u64())
+    }
+
+    async fn drips_events(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<DripsEvent>> {
+        let provider = self.provider(chain_id)?;
+        let mut topics = vec![];
+        for name in DRIPS_EVENTS {
+            topics.push(
+                self.drips_hub
+                    .abi()
+                    .event(name)
+                    .map_err(rpc_error)?
+                    .signature(),
+            );
+        }
+        let filter = Filter::new()
+            .address(drips_hub)
+            .topic0(ValueOrArray::Array(topics))
+            .from_block(from)
+            .to_block(to);
+        let logs = provider.get_logs(&filter).await.map_err(rpc_error)?;
+        // block number => timestamp, as the logs of a block share its timestamp
+        let mut timestamps: HashMap<u64, u64> = HashMap::new();
+        let mut events = vec![];
+        for log in logs {
+            let block_number = log
+                .block_number
+                .ok_or_else(|| Error::from("rpc: DripsHub log is pending"))?
+                .as_u64();
+            let timestamp = match timestamps.get(&block_number) {
+                Some(timestamp) => *timestamp,
+                None => {
+                    let block = provider
+                        .get_block(block_number)
+                        .await
+                        .map_err(rpc_error)?
+                        .ok_or_else(|| Error::from("rpc: unknown block"))?;
+                    timestamps.insert(block_number, block.timestamp.as_u64());
+                    block.timestamp.as_u64()
+                }
+            };
+            if let Some(event) = decode_drips_log(&self.drips_hub, log, timestamp)? {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    /// The events are read up to the latest block, `MAX_BLOCKS` blocks per request.
+    async fn collected(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        start_block: u64,
+        user: Address,
+    ) -> Result<U256> {
+        let provider = self.provider(chain_id)?;
+        let event = self.drips_hub.abi().event("Collected").map_err(rpc_error)?;
+        let filter = Filter::new()
+            .address(drips_hub)
+            .topic0(event.signature())
+            .topic1(H256::from(user));
+        let latest = self.block_number(chain_id).await?;
+        let mut total = U256::zero();
+        let mut from = start_block;
+        while from <= latest {
+            let to = latest.min(from + MAX_BLOCKS - 1);
+            let logs = provider
+                .get_logs(&filter.clone().from_block(from).to_block(to))
+                .await
+                .map_err(rpc_error)?;
+            for log in logs {
+                let (_, collected, _): (Address, u128, u128) = self
+                    .drips_hub
+                    .decode_event("Collected", log.topics, log.data)
+                    .map_err(rpc_error)?;
+                total = total.saturating_add(U256::from(collected));
+            }
+            from = to + 1;
+        }
+        Ok(total)
+    }
+}
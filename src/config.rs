@@ -0,0 +1,122 @@
+use super::chain::MAINNET;
+use super::cookies::{CookieOptions, SameSite};
+use super::validation::Limits;
+use std::collections::HashMap;
+use std::str::FromStr;
+use worker::{Env, Error, Result};
+
+/// The configuration of the API, read from the environment variables of the worker (`[vars]` in
+/// `wrangler.toml`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The domain that EIP4361 messages must be issued for (`SIWE_DOMAIN`), e.g `example.com`.
+    pub domain: String,
+    /// The URI that EIP4361 messages must be issued for (`SIWE_URI`), e.g `https://example.com`.
+    pub uri: String,
+    /// The chains that the API runs on (`SIWE_CHAIN_ID`, a comma separated list, e.g `1,10`).
+    /// EIP4361 messages must be issued for one of them and workstreams can only run on them.
+    pub chain_ids: Vec<u64>,
+    /// The URLs of the JSON-RPC nodes of the chains: `RPC_URL_{chain id}`, e.g `RPC_URL_10`, or
+    /// `RPC_URL` for the first chain of `chain_ids`.
+    pub rpc_urls: HashMap<u64, String>,
+    /// How many seconds a nonce can be used for, after it's issued (`NONCE_TTL`). Defaults to
+    /// 10 minutes. The nonces that aren't used are deleted by the cron triggers of the worker
+    /// once they expire.
+    pub nonce_ttl: u64,
+    /// The attributes of the session cookie (`COOKIE_DOMAIN`, `COOKIE_SAMESITE`). By default,
+    /// the cookie is only sent to the domain of the API, with `SameSite=Lax`.
+    pub cookies: CookieOptions,
+    /// The limits of the workstreams and the applications (`MAX_TITLE_LENGTH`,
+    /// `MAX_DESCRIPTION_LENGTH`, `MAX_RECEIVERS`, `MAX_PAYMENT_RATE`). Every limit that isn't
+    /// set keeps its default.
+    pub limits: Limits,
+}
+
+impl Config {
+    pub const DEFAULT_NONCE_TTL: u64 = 600;
+
+    pub fn from_env(env: &Env) -> Result<Config> {
+        let nonce_ttl = match optional_var(env, "NONCE_TTL") {
+            Some(ttl) => parse_var("NONCE_TTL", &ttl)?,
+            None => Config::DEFAULT_NONCE_TTL,
+        };
+        let same_site = match optional_var(env, "COOKIE_SAMESITE") {
+            Some(same_site) => SameSite::from_str(&same_site)?,
+            None => SameSite::default(),
+        };
+        let defaults = Limits::default();
+        let limit = |name: &str, default| match optional_var(env, name) {
+            Some(value) => parse_var(name, &value),
+            None => Ok(default),
+        };
+        let limits = Limits {
+            max_title_length: limit("MAX_TITLE_LENGTH", defaults.max_title_length)?,
+            max_description_length: limit(
+                "MAX_DESCRIPTION_LENGTH",
+                defaults.max_description_length,
+            )?,
+            max_receivers: limit("MAX_RECEIVERS", defaults.max_receivers)?,
+            max_payment_rate: match optional_var(env, "MAX_PAYMENT_RATE") {
+                Some(value) => parse_var("MAX_PAYMENT_RATE", &value)?,
+                None => defaults.max_payment_rate,
+            },
+        };
+        let chain_ids = env
+            .var("SIWE_CHAIN_ID")?
+            .to_string()
+            .split(',')
+            .map(|x| parse_var("SIWE_CHAIN_ID", x.trim()))
+            .collect::<Result<Vec<u64>>>()?;
+        let mut rpc_urls = HashMap::new();
+        for (i, chain_id) in chain_ids.iter().enumerate() {
+            let url = match optional_var(env, &format!("RPC_URL_{}", chain_id)) {
+                Some(url) => Some(url),
+                None if i == 0 => optional_var(env, "RPC_URL"),
+                None => None,
+            };
+            if let Some(url) = url {
+                rpc_urls.insert(*chain_id, url);
+            }
+        }
+        Ok(Config {
+            domain: env.var("SIWE_DOMAIN")?.to_string(),
+            uri: env.var("SIWE_URI")?.to_string(),
+            chain_ids,
+            rpc_urls,
+            nonce_ttl,
+            cookies: CookieOptions {
+                domain: optional_var(env, "COOKIE_DOMAIN"),
+                same_site,
+            },
+            limits,
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            domain: "localhost".to_owned(),
+            uri: "http://localhost".to_owned(),
+            chain_ids: vec![MAINNET],
+            rpc_urls: HashMap::new(),
+            nonce_ttl: Config::DEFAULT_NONCE_TTL,
+            cookies: CookieOptions::default(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Returns the value of an environment variable, or `None` if it's not set or empty.
+fn optional_var(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|x| x.to_string())
+        .filter(|x| !x.is_empty())
+}
+
+fn parse_var<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::from(format!("{} must be a positive integer", name)))
+}
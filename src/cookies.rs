@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use worker::Error;
+
+/// The name of the cookie that holds the authorization token.
+pub const SESSION_COOKIE: &str = "SIWE-AUTH";
+
+/// The `SameSite` attribute of the session cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Default for SameSite {
+    fn default() -> Self {
+        SameSite::Lax
+    }
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = worker::Error;
+    fn from_str(input: &str) -> Result<Self, worker::Error> {
+        match input.to_lowercase().as_ref() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(Error::from("can't parse SameSite")),
+        }
+    }
+}
+
+/// The attributes of the session cookie, configured with the `COOKIE_DOMAIN` and
+/// `COOKIE_SAMESITE` environment variables.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CookieOptions {
+    /// If set, the cookie is also sent to the subdomains of `domain`.
+    pub domain: Option<String>,
+    pub same_site: SameSite,
+}
+
+/// Parses the value of a `Cookie` header (`name1=value1; name2=value2`) into a map from the name
+/// of every cookie to its value.
+pub fn parse(header: &str) -> HashMap<String, String> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let value = value.trim().trim_matches('"');
+            Some((name.trim().to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Builds the `Set-Cookie` header value of the session cookie that holds `token`. The cookie
+/// expires together with the authorization; without an `expiration`, it's a session cookie that
+/// is dropped when the browser closes.
+pub fn session_cookie(
+    token: &str,
+    expiration: Option<DateTime<Utc>>,
+    options: &CookieOptions,
+) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Secure; HttpOnly; SameSite={}",
+        SESSION_COOKIE, token, options.same_site
+    );
+    if let Some(domain) = &options.domain {
+        cookie.push_str(&format!("; Domain={}", domain));
+    }
+    if let Some(expiration) = expiration {
+        let max_age = (expiration - Utc::now()).num_seconds().max(0);
+        cookie.push_str(&format!(
+            "; Expires={}; Max-Age={}",
+            expiration.format("%a, %d %b %Y %H:%M:%S GMT"),
+            max_age
+        ));
+    }
+    cookie
+}
+
+/// Builds the `Set-Cookie` header value that makes the browser drop the session cookie.
+pub fn expired_session_cookie(options: &CookieOptions) -> String {
+    let mut cookie = format!(
+        "{}=; Path=/; Secure; HttpOnly; SameSite={}",
+        SESSION_COOKIE, options.same_site
+    );
+    if let Some(domain) = &options.domain {
+        cookie.push_str(&format!("; Domain={}", domain));
+    }
+    cookie.push_str("; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0");
+    cookie
+}
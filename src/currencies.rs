@@ -0,0 +1,54 @@
+use super::error::{ApiError, ApiResult};
+use super::store::Store;
+use super::workstreams::PaymentCurrency;
+use ethers::types::Address;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A currency that workstreams can be paid in, on a chain. The supported currencies form a
+/// registry, which is stored in the `DRIPSHUBS` namespace, keyed by `chain_id` and `symbol`, and
+/// is managed by the operators of the API. A workstream can only be created in a currency of the
+/// registry, on the chain that the currency is registered on.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct Currency {
+    /// The symbol of the currency, e.g `DAI`. It's the `payment_currency` of the workstreams.
+    pub symbol: PaymentCurrency,
+    /// The address of the ERC-20 contract of the currency.
+    #[schemars(with = "String")]
+    pub address: Address,
+    /// The number of decimals of the ERC-20 contract.
+    pub decimals: u8,
+    /// The chain that the ERC-20 and DripsHub contracts are deployed on.
+    pub chain_id: u64,
+    /// The address of the DripsHub contract that streams the currency.
+    #[schemars(with = "String")]
+    pub drips_hub: Address,
+    /// The block that the indexer starts reading the events of the DripsHub from, e.g the block
+    /// that it was deployed in.
+    #[serde(default)]
+    pub start_block: u64,
+    /// The maximum payment rate of a receiver in this currency, in units of the currency (not
+    /// its smallest unit) per second. The `max_payment_rate` of the `Limits` applies if it's
+    /// `null`.
+    #[serde(default)]
+    pub max_payment_rate: Option<u64>,
+}
+
+/// Looks up the currency with the given symbol on `chain_id`. A currency that isn't in the
+/// registry is an invalid `payment_currency`.
+pub async fn lookup(
+    store: &dyn Store,
+    chain_id: u64,
+    symbol: &PaymentCurrency,
+) -> ApiResult<Currency> {
+    match store.get_currency(chain_id, symbol).await? {
+        Some(currency) => Ok(currency),
+        None => Err(ApiError::invalid(
+            "payment_currency",
+            format!(
+                "unsupported currency on chain {}, see /api/v1/currencies",
+                chain_id
+            ),
+        )),
+    }
+}
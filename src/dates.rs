@@ -0,0 +1,91 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Parses the dates of the filters and of the payloads: RFC 3339 (`2022-03-05T12:17:31Z`), plain
+/// dates (`2022-03-05`, at midnight UTC), RFC 2822 and the formats that the dates of the first
+/// workstreams were stored in: the one of JavaScript's `Date.prototype.toString`
+/// (`Sat Mar 05 2022 12:17:31 GMT+0000 (Coordinated Universal Time)`) and
+/// `March 5, 2022 12:17:31 GMT`.
+pub fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Some(date.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Some(Utc.from_utc_datetime(&date.and_hms(0, 0, 0)));
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+        return Some(date.with_timezone(&Utc));
+    }
+    // `Date.prototype.toString` of JavaScript, which ends with the name of the time zone
+    let js_date = match date.find(" (") {
+        Some(end) if date.ends_with(')') => &date[..end],
+        _ => date,
+    };
+    if let Ok(date) = DateTime::parse_from_str(js_date, "%a %b %d %Y %H:%M:%S GMT%z") {
+        return Some(date.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(date, "%B %d, %Y %H:%M:%S GMT")
+        .ok()
+        .map(|date| Utc.from_utc_datetime(&date))
+}
+
+/// Formats a date the way that all the dates of the API are returned: RFC 3339, in UTC, with
+/// milliseconds (`2022-03-05T12:17:31.000Z`). Dates in this format sort chronologically as
+/// strings.
+pub fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The default of the dates that are populated by the API, like `created_at`.
+pub fn epoch() -> DateTime<Utc> {
+    Utc.timestamp(0, 0)
+}
+
+/// Serializes a date with `format_date` and deserializes it with `parse_date`, so that the dates
+/// that were stored, or sent by clients, in a legacy format can still be read.
+/// Use it with `#[serde(with = "dates::lenient")]`.
+pub mod lenient {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        date: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_date(date))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let date = String::deserialize(deserializer)?;
+        parse_date(&date).ok_or_else(|| D::Error::custom(format!("invalid date: {}", date)))
+    }
+}
+
+/// The same as `lenient`, for optional dates. Use it with
+/// `#[serde(default, with = "dates::lenient_option")]`.
+pub mod lenient_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => serializer.serialize_some(&format_date(date)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(date) => parse_date(&date)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom(format!("invalid date: {}", date))),
+            None => Ok(None),
+        }
+    }
+}
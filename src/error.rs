@@ -0,0 +1,167 @@
+use super::http::ApiResponse;
+use super::validation::Violation;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+
+/// The errors that the API responds with. Every kind of error is mapped to an HTTP status and to
+/// a stable `code`, which is sent in a JSON body together with a human readable message:
+///
+/// ```json
+/// { "code": "validation_error", "message": "can't parse Workstream State", "field": "state" }
+/// ```
+///
+/// Clients are expected to switch on `code`, never on `message`, which can change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiError {
+    /// The request is malformed or one of its values is invalid (`400`). `field` names the field
+    /// of the body, or the parameter, that is invalid, if there's a single one.
+    Validation {
+        message: String,
+        field: Option<String>,
+    },
+    /// The payload of the request has one or more invalid fields (`400`). It's a validation
+    /// error, whose body lists all the `violations`.
+    Violations(Vec<Violation>),
+    /// The request doesn't carry a valid authorization token (`401`).
+    Unauthorized(String),
+    /// The token of the request is valid, but it can't access the resource (`403`).
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed,
+    /// The request conflicts with the current state of the resource (`409`).
+    Conflict(String),
+    /// The `If-Match` header of the request doesn't match the `ETag` of the resource (`412`).
+    PreconditionFailed(String),
+    /// The request must be conditional, with `If-Match` (`428`).
+    PreconditionRequired(String),
+    /// A service that the API depends on, like the RPC endpoint of the chain, failed (`502`).
+    Upstream(String),
+    /// An unexpected error of the API itself, e.g of the store (`500`).
+    Internal(String),
+}
+
+/// The result of the route handlers and of the logic that they call into.
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// The JSON body of an error response.
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "Error")]
+pub(crate) struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    field: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    violations: Option<&'a [Violation]>,
+}
+
+impl ApiError {
+    /// A validation error that isn't tied to a single field.
+    pub fn validation<T: Into<String>>(message: T) -> ApiError {
+        ApiError::Validation {
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    /// A validation error of a single field of the body, or parameter, of the request.
+    pub fn invalid<T: Into<String>>(field: &str, message: T) -> ApiError {
+        ApiError::Validation {
+            message: message.into(),
+            field: Some(field.to_owned()),
+        }
+    }
+
+    pub fn upstream<T: fmt::Display>(err: T) -> ApiError {
+        ApiError::Upstream(err.to_string())
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::Validation { .. } | ApiError::Violations(_) => 400,
+            ApiError::Unauthorized(_) => 401,
+            ApiError::Forbidden(_) => 403,
+            ApiError::NotFound(_) => 404,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::Conflict(_) => 409,
+            ApiError::PreconditionFailed(_) => 412,
+            ApiError::PreconditionRequired(_) => 428,
+            ApiError::Internal(_) => 500,
+            ApiError::Upstream(_) => 502,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation { .. } | ApiError::Violations(_) => "validation_error",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::PreconditionRequired(_) => "precondition_required",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Upstream(_) => "upstream_error",
+        }
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            ApiError::Validation { field, .. } => field.as_deref(),
+            ApiError::Violations(violations) if violations.len() == 1 => Some(&violations[0].field),
+            _ => None,
+        }
+    }
+
+    /// Converts the error into the response that is sent to the client.
+    pub fn into_response(self) -> ApiResponse {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            field: self.field(),
+            violations: match &self {
+                ApiError::Violations(violations) => Some(violations),
+                _ => None,
+            },
+        };
+        ApiResponse {
+            status: self.status(),
+            headers: vec![("Content-Type".to_owned(), "application/json".to_owned())],
+            body: serde_json::to_string(&body).unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Validation { message, .. }
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::PreconditionRequired(message)
+            | ApiError::Upstream(message)
+            | ApiError::Internal(message) => write!(f, "{}", message),
+            ApiError::Violations(violations) => {
+                let violations: Vec<String> = violations
+                    .iter()
+                    .map(|x| format!("{}: {}", x.field, x.message))
+                    .collect();
+                write!(f, "invalid payload: {}", violations.join(", "))
+            }
+            ApiError::MethodNotAllowed => write!(f, "HTTP Method Not Allowed"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The errors of the Cloudflare runtime, e.g of KV, are unexpected.
+impl From<worker::Error> for ApiError {
+    fn from(err: worker::Error) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,127 @@
+use super::dates::parse_date;
+use super::error::{ApiError, ApiResult};
+use super::pagination;
+use super::store::WorkstreamIndex;
+use super::workstreams::{PaymentCurrency, WorkstreamState, WorkstreamType};
+use chrono::{DateTime, Utc};
+use ethers::types::Address;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The query string parameters that filter the workstream listing.
+pub const PARAMETERS: &[&str] = &[
+    "state",
+    "wtype",
+    "creator",
+    "payment_currency",
+    "chain_id",
+    "starting_after",
+    "starting_before",
+    "ending_after",
+    "ending_before",
+    "receiver",
+    "q",
+];
+
+/// The filters of `/api/v1/workstreams`, parsed from the query string of the request. A
+/// workstream is listed only if it matches all of the filters that are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkstreamFilter {
+    pub state: Option<WorkstreamState>,
+    pub wtype: Option<WorkstreamType>,
+    pub creator: Option<Address>,
+    pub payment_currency: Option<PaymentCurrency>,
+    pub chain_id: Option<u64>,
+    /// The workstream must start at or after this moment.
+    pub starting_after: Option<DateTime<Utc>>,
+    /// The workstream must start at or before this moment.
+    pub starting_before: Option<DateTime<Utc>>,
+    /// The workstream must end at or after this moment.
+    pub ending_after: Option<DateTime<Utc>>,
+    /// The workstream must end at or before this moment.
+    pub ending_before: Option<DateTime<Utc>>,
+    /// The workstream must stream funds to this address.
+    pub receiver: Option<Address>,
+    /// Lowercase text that the title or the description of the workstream must contain.
+    pub text: Option<String>,
+}
+
+impl WorkstreamFilter {
+    /// Parses the filters out of the query string of a request, as returned by
+    /// `parse_query_string`. Besides the filters, only the pagination parameters are allowed, so
+    /// that a typo doesn't silently return every workstream.
+    pub fn from_query(query: &HashMap<String, String>) -> ApiResult<WorkstreamFilter> {
+        if let Some(key) = query.keys().find(|key| {
+            !PARAMETERS.contains(&key.as_str()) && !pagination::PARAMETERS.contains(&key.as_str())
+        }) {
+            return Err(ApiError::invalid(
+                key,
+                format!("unknown query parameter: {}", key),
+            ));
+        }
+        let date = |key: &str| -> ApiResult<Option<DateTime<Utc>>> {
+            match query.get(key) {
+                Some(value) => parse_date(value).map(Some).ok_or_else(|| {
+                    ApiError::invalid(key, format!("{} must be an RFC 3339 date", key))
+                }),
+                None => Ok(None),
+            }
+        };
+        let address = |key: &str| -> ApiResult<Option<Address>> {
+            match query.get(key) {
+                Some(value) => Address::from_str(value)
+                    .map(Some)
+                    .map_err(|_| ApiError::invalid(key, format!("{} must be an address", key))),
+                None => Ok(None),
+            }
+        };
+        let filter = WorkstreamFilter {
+            state: query
+                .get("state")
+                .map(|x| WorkstreamState::from_str(x))
+                .transpose()?,
+            wtype: query
+                .get("wtype")
+                .map(|x| WorkstreamType::from_str(x))
+                .transpose()?,
+            creator: address("creator")?,
+            payment_currency: query
+                .get("payment_currency")
+                .map(|x| PaymentCurrency::from_str(x))
+                .transpose()?,
+            chain_id: query
+                .get("chain_id")
+                .map(|x| {
+                    x.parse::<u64>()
+                        .map_err(|_| ApiError::invalid("chain_id", "chain_id must be a chain id"))
+                })
+                .transpose()?,
+            starting_after: date("starting_after")?,
+            starting_before: date("starting_before")?,
+            ending_after: date("ending_after")?,
+            ending_before: date("ending_before")?,
+            receiver: address("receiver")?,
+            text: query
+                .get("q")
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty()),
+        };
+        if matches!((filter.starting_after, filter.starting_before), (Some(after), Some(before)) if after > before)
+            || matches!((filter.ending_after, filter.ending_before), (Some(after), Some(before)) if after > before)
+        {
+            return Err(ApiError::validation("the date ranges must not be empty"));
+        }
+        Ok(filter)
+    }
+
+    /// The index that the workstreams are listed from: the most selective one that the filters
+    /// can use. The rest of the filters are applied on the listed workstreams.
+    pub fn index(&self) -> WorkstreamIndex {
+        match (&self.creator, &self.payment_currency, &self.state) {
+            (Some(creator), _, _) => WorkstreamIndex::Creator(*creator),
+            (_, Some(currency), _) => WorkstreamIndex::Currency(currency.clone()),
+            (_, _, Some(state)) => WorkstreamIndex::State(*state),
+            _ => WorkstreamIndex::Id,
+        }
+    }
+}
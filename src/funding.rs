@@ -0,0 +1,115 @@
+use super::chain::{Chain, DripsState};
+use super::dates;
+use super::rates::decimal;
+use super::workstreams::Workstream;
+use chrono::{DateTime, TimeZone, Utc};
+use ethers::types::{Address, U256};
+use schemars::JsonSchema;
+use serde::Serialize;
+use worker::Result;
+
+/// The funding of a workstream, as it's read from its DripsHub: how much is left in its drips
+/// account and how long it lasts. The amounts are in the smallest unit of the currency of the
+/// workstream, as decimal strings.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Funding {
+    pub workstream_id: String,
+    /// The balance that is left in the drips account at `as_of`.
+    #[serde(with = "decimal")]
+    #[schemars(with = "String")]
+    pub balance: U256,
+    /// The amount that the account streams to all of its receivers per second.
+    #[serde(with = "decimal")]
+    #[schemars(with = "String")]
+    pub amount_per_second: U256,
+    /// When the account runs out of funds, at its current outflow. It's `null` if the account
+    /// doesn't stream to anyone.
+    #[serde(with = "dates::lenient_option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(with = "dates::lenient")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub as_of: DateTime<Utc>,
+    /// The receivers that the account streams to on-chain, which can differ from the receivers of
+    /// the workstream until its drips configuration is updated.
+    pub receivers: Vec<ReceiverFunding>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ReceiverFunding {
+    #[schemars(with = "String")]
+    pub address: Address,
+    #[serde(with = "decimal")]
+    #[schemars(with = "String")]
+    pub amount_per_second: U256,
+    /// The total amount that the receiver has collected from the DripsHub, from all of its
+    /// senders, so including the funds of other workstreams: DripsHub doesn't tell the senders
+    /// apart once the funds are collected.
+    #[serde(with = "decimal")]
+    #[schemars(with = "String")]
+    pub total_collected: U256,
+}
+
+impl Funding {
+    /// Reads the funding of a workstream from its DripsHub, which was deployed in `start_block`.
+    /// The drips account of a workstream that has never been configured is empty.
+    pub async fn of(
+        workstream: &Workstream,
+        start_block: u64,
+        chain: &dyn Chain,
+    ) -> Result<Funding> {
+        let (creator, account) = workstream.drips_account();
+        let drips = chain
+            .drips_state(
+                workstream.chain_id(),
+                workstream.drips_hub(),
+                start_block,
+                creator,
+                account,
+            )
+            .await?
+            .unwrap_or(DripsState {
+                update_time: 0,
+                balance: U256::zero(),
+                receivers: vec![],
+            });
+        let as_of = Utc::now();
+        let mut receivers = vec![];
+        for (address, amount_per_second) in &drips.receivers {
+            receivers.push(ReceiverFunding {
+                address: *address,
+                amount_per_second: *amount_per_second,
+                total_collected: chain
+                    .collected(
+                        workstream.chain_id(),
+                        workstream.drips_hub(),
+                        start_block,
+                        *address,
+                    )
+                    .await?,
+            });
+        }
+        Ok(Funding {
+            workstream_id: workstream.id.clone(),
+            balance: drips.balance_at(as_of.timestamp().unsigned_abs()),
+            amount_per_second: drips.amount_per_second(),
+            ends_at: ends_at(&drips),
+            as_of,
+            receivers,
+        })
+    }
+}
+
+/// When a drips account runs out of funds: DripsHub streams whole amounts per second, so it's
+/// the last second that the balance covers the outflow of the account in full.
+fn ends_at(drips: &DripsState) -> Option<DateTime<Utc>> {
+    let outflow = drips.amount_per_second();
+    if outflow.is_zero() {
+        return None;
+    }
+    let end = U256::from(drips.update_time).saturating_add(drips.balance / outflow);
+    if end > U256::from(i64::MAX as u64) {
+        return None;
+    }
+    Utc.timestamp_opt(end.as_u64() as i64, 0).single()
+}
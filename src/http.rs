@@ -0,0 +1,137 @@
+use super::error::{ApiError, ApiResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use worker::{Error, Headers, Method, Request, Response, Result, Url};
+
+/// A runtime-agnostic HTTP request. The worker converts every incoming worker::Request into an
+/// ApiRequest before passing it to the route handlers, so that the handlers can be driven
+/// natively as well (e.g from the tests), without a Cloudflare runtime.
+#[derive(Debug, Clone)]
+pub struct ApiRequest {
+    pub method: Method,
+    pub url: Url,
+    /// Header names are stored in lowercase, as HTTP headers are case-insensitive.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+    /// The parameters that are encoded in the path (e.g `:user`). They are populated by the
+    /// router, once the path is matched against a route.
+    pub params: HashMap<String, String>,
+}
+
+impl ApiRequest {
+    pub fn new(method: Method, url: &str) -> Result<ApiRequest> {
+        Ok(ApiRequest {
+            method,
+            url: Url::parse(url).map_err(|err| Error::from(err.to_string()))?,
+            headers: HashMap::new(),
+            body: String::new(),
+            params: HashMap::new(),
+        })
+    }
+
+    /// Converts a worker::Request into an ApiRequest, consuming the body of the request.
+    pub async fn from_worker(req: &mut Request) -> Result<ApiRequest> {
+        let headers = req
+            .headers()
+            .entries()
+            .map(|(name, value)| (name.to_lowercase(), value))
+            .collect();
+        Ok(ApiRequest {
+            method: req.method(),
+            url: req.url()?,
+            headers,
+            body: req.text().await?,
+            params: HashMap::new(),
+        })
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> ApiRequest {
+        self.headers.insert(name.to_lowercase(), value.to_owned());
+        self
+    }
+
+    pub fn with_body<T: Into<String>>(mut self, body: T) -> ApiRequest {
+        self.body = body.into();
+        self
+    }
+
+    pub fn with_json<T: Serialize>(self, body: &T) -> Result<ApiRequest> {
+        Ok(self.with_body(serde_json::to_string(body)?))
+    }
+
+    pub fn path(&self) -> &str {
+        self.url.path()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(&name.to_lowercase())
+    }
+
+    pub fn param(&self, name: &str) -> Option<&String> {
+        self.params.get(name)
+    }
+
+    /// Deserializes the body of the request, which is expected to be a JSON object. A body that
+    /// doesn't match `T` is a validation error.
+    pub fn json<T: DeserializeOwned>(&self) -> ApiResult<T> {
+        serde_json::from_str(&self.body)
+            .map_err(|err| ApiError::validation(format!("invalid body: {}", err)))
+    }
+}
+
+/// A runtime-agnostic HTTP response, returned by the route handlers. It's converted into a
+/// worker::Response before it's returned by the worker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ApiResponse {
+    pub fn ok<T: Into<String>>(body: T) -> ApiResult<ApiResponse> {
+        Ok(ApiResponse {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        })
+    }
+
+    pub fn from_json<T: Serialize>(value: &T) -> ApiResult<ApiResponse> {
+        Ok(ApiResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_owned(), "application/json".to_owned())],
+            body: serde_json::to_string(value)
+                .map_err(|err| ApiError::Internal(err.to_string()))?,
+        })
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> ApiResponse {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Deserializes the body of the response, which is expected to be a JSON object.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_str(&self.body).map_err(Error::from)
+    }
+
+    pub fn into_worker(self) -> Result<Response> {
+        let mut headers = Headers::new();
+        for (name, value) in &self.headers {
+            headers.append(name, value)?;
+        }
+        Ok(Response::ok(self.body)?
+            .with_status(self.status)
+            .with_headers(headers))
+    }
+}
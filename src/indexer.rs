@@ -0,0 +1,261 @@
+use super::chain::{Chain, DripsEvent, DripsEventKind, DripsState};
+use super::config::Config;
+use super::store::{Store, WorkstreamIndex};
+use super::utils::log;
+use super::workstreams::{Workstream, WorkstreamState};
+use chrono::Utc;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use worker::Result;
+
+/// The number of blocks that the indexer stays behind the latest block of a chain, so that it
+/// doesn't record the events of blocks that are then reorganized.
+pub const CONFIRMATIONS: u64 = 12;
+
+/// The maximum number of blocks that the indexer reads from a DripsHub in a run. JSON-RPC nodes
+/// limit the range of `eth_getLogs`, so a DripsHub that is far behind is caught up over several
+/// runs.
+pub const MAX_BLOCKS: u64 = 2_000;
+
+/// The funding history of a workstream: the events of its DripsHub that concern its drips
+/// account (`DripsUpdated`, `Given`) or its receivers (`Collected`), in the order of the chain.
+///
+/// A ledger is started when the indexer first sees its workstream, which can be long after the
+/// DripsHub was indexed past the events of the workstream, e.g if the drips account was funded
+/// before the workstream was created. The ledger is then backfilled from the `start_block` of
+/// its currency, a range of blocks per run, until it catches up with its DripsHub.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Ledger {
+    pub workstream_id: String,
+    pub entries: Vec<DripsEvent>,
+    /// The receivers whose `Collected` events are recorded.
+    #[serde(default)]
+    pub receivers: Vec<Address>,
+    /// The next block to backfill the ledger from, or `None` once the ledger has caught up and
+    /// follows the blocks of its DripsHub as they are indexed.
+    #[serde(default)]
+    pub backfill_from: Option<u64>,
+}
+
+impl Ledger {
+    /// Starts the ledger of a workstream, which is backfilled from `start_block`.
+    pub fn new(workstream: &Workstream, start_block: u64) -> Ledger {
+        Ledger {
+            workstream_id: workstream.id.clone(),
+            entries: vec![],
+            receivers: workstream.receiver_addresses(),
+            backfill_from: Some(start_block),
+        }
+    }
+
+    /// Backfills the ledger from `start_block` again if receivers were added to the workstream,
+    /// as their earlier `Collected` events were never recorded. It returns `true` if it did.
+    fn follow_receivers(&mut self, workstream: &Workstream, start_block: u64) -> bool {
+        let receivers = workstream.receiver_addresses();
+        if receivers.iter().all(|x| self.receivers.contains(x)) {
+            return false;
+        }
+        self.receivers = receivers;
+        self.backfill_from = Some(start_block);
+        true
+    }
+
+    /// Records an event in the order of the chain, unless it's already recorded, as a run that
+    /// fails half-way reads its blocks again. Backfilled events can precede the recorded ones.
+    /// It returns `true` if the event is new.
+    pub fn record(&mut self, event: &DripsEvent) -> bool {
+        let position = (event.block_number, event.log_index);
+        if self
+            .entries
+            .iter()
+            .any(|entry| (entry.block_number, entry.log_index) == position)
+        {
+            return false;
+        }
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| (entry.block_number, entry.log_index) > position)
+            .unwrap_or_else(|| self.entries.len());
+        self.entries.insert(index, event.clone());
+        true
+    }
+
+    /// The configuration of the drips account, as it was set by its last `DripsUpdated` event.
+    pub fn drips_state(&self) -> Option<DripsState> {
+        self.entries
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.kind {
+                DripsEventKind::DripsUpdated {
+                    balance, receivers, ..
+                } => Some(DripsState {
+                    update_time: entry.timestamp,
+                    balance: *balance,
+                    receivers: receivers.clone(),
+                }),
+                _ => None,
+            })
+    }
+}
+
+/// Checks if an event of the DripsHub of a workstream belongs to its ledger.
+fn concerns(workstream: &Workstream, event: &DripsEvent) -> bool {
+    match &event.kind {
+        DripsEventKind::DripsUpdated { user, account, .. }
+        | DripsEventKind::Given { user, account, .. } => {
+            workstream.drips_account() == (*user, *account)
+        }
+        DripsEventKind::Collected { user, .. } => workstream.has_receiver(user),
+    }
+}
+
+/// Runs the indexer once. It's triggered by the cron triggers of the worker (`[triggers]` in
+/// `wrangler.toml`).
+///
+/// For every DripsHub of the registry of currencies, on the chains of the API, the indexer reads
+/// the events that were emitted since its last run, records them in the ledgers of the
+/// workstreams that they concern, backfills the ledgers that are behind (see `Ledger`) and moves
+/// the workstreams that are `Open` or `Funded` to the state that their funding calls for (see
+/// `Workstream::follow_funding()`). The last indexed block of every DripsHub is stored, so every
+/// run picks up where the previous one stopped.
+///
+/// A DripsHub that can't be indexed, e.g because its node fails, doesn't stop the others. It's
+/// indexed again on the next run.
+pub async fn run(store: &dyn Store, chain: &dyn Chain, config: &Config) -> Result<()> {
+    let workstreams = list_workstreams(store).await?;
+    // (chain id, DripsHub) => start block
+    let mut drips_hubs: HashMap<(u64, Address), u64> = HashMap::new();
+    for currency in store.list_currencies().await? {
+        if config.chain_ids.contains(&currency.chain_id) {
+            let start_block = drips_hubs
+                .entry((currency.chain_id, currency.drips_hub))
+                .or_insert(currency.start_block);
+            *start_block = currency.start_block.min(*start_block);
+        }
+    }
+    for ((chain_id, drips_hub), start_block) in drips_hubs {
+        let workstreams: Vec<&Workstream> = workstreams
+            .iter()
+            .filter(|x| x.chain_id() == chain_id && x.drips_hub() == drips_hub)
+            .collect();
+        let result = index(store, chain, chain_id, drips_hub, start_block, &workstreams).await;
+        if let Err(err) = result {
+            log(&format!(
+                "indexer: DripsHub {:?} on chain {} failed: {}",
+                drips_hub, chain_id, err
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Indexes the next blocks of a DripsHub, whose workstreams are `workstreams`, and backfills
+/// the ledgers that are behind it.
+async fn index(
+    store: &dyn Store,
+    chain: &dyn Chain,
+    chain_id: u64,
+    drips_hub: Address,
+    start_block: u64,
+    workstreams: &[&Workstream],
+) -> Result<()> {
+    let head = chain
+        .block_number(chain_id)
+        .await?
+        .saturating_sub(CONFIRMATIONS);
+    let from = match store.get_indexed_block(chain_id, &drips_hub).await? {
+        Some(block) => block + 1,
+        None => start_block,
+    };
+    // (last block, events) of the blocks that are indexed in this run
+    let indexed = if from <= head {
+        let to = head.min(from + MAX_BLOCKS - 1);
+        Some((to, chain.drips_events(chain_id, drips_hub, from, to).await?))
+    } else {
+        None
+    };
+    let mut ledgers: HashMap<String, Ledger> = HashMap::new();
+    for workstream in workstreams {
+        let (mut ledger, mut changed) = match store.get_ledger(&workstream.id).await? {
+            Some(ledger) => (ledger, false),
+            None => (Ledger::new(workstream, start_block), true),
+        };
+        changed |= ledger.follow_receivers(workstream, start_block);
+        if let Some(backfill_from) = ledger.backfill_from {
+            if backfill_from < from {
+                let to = (from - 1).min(backfill_from + MAX_BLOCKS - 1);
+                let events = chain
+                    .drips_events(chain_id, drips_hub, backfill_from, to)
+                    .await?;
+                for event in events.iter().filter(|event| concerns(workstream, event)) {
+                    ledger.record(event);
+                }
+                ledger.backfill_from = Some(to + 1);
+            }
+            if ledger.backfill_from >= Some(from) {
+                ledger.backfill_from = None;
+            }
+            changed = true;
+        }
+        if let (None, Some((_, events))) = (ledger.backfill_from, &indexed) {
+            for event in events.iter().filter(|event| concerns(workstream, event)) {
+                changed |= ledger.record(event);
+            }
+        }
+        if changed {
+            store.put_ledger(&ledger).await?;
+        }
+        ledgers.insert(workstream.id.clone(), ledger);
+    }
+    if let Some((to, _)) = indexed {
+        store.put_indexed_block(chain_id, &drips_hub, to).await?;
+    }
+    // the balance of an account runs out over time, so the states are followed on every run.
+    // The ledgers that are still backfilled can miss the last configuration of their account.
+    let now = Utc::now().timestamp().unsigned_abs();
+    for workstream in workstreams {
+        if !matches!(
+            workstream.state,
+            WorkstreamState::Open | WorkstreamState::Funded
+        ) {
+            continue;
+        }
+        let drips = match ledgers
+            .get(&workstream.id)
+            .filter(|ledger| ledger.backfill_from.is_none())
+            .and_then(Ledger::drips_state)
+        {
+            Some(drips) => drips,
+            None => continue,
+        };
+        let mut workstream = (*workstream).clone();
+        if Workstream::follow_funding(&mut workstream, &drips, now) {
+            // a workstream that changed meanwhile is followed on the next run
+            if !store.put_workstream(&workstream).await? {
+                log(&format!(
+                    "indexer: workstream {} changed while it was followed",
+                    workstream.id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists all the workstreams, from the `id` index.
+async fn list_workstreams(store: &dyn Store) -> Result<Vec<Workstream>> {
+    let mut workstreams = vec![];
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = store
+            .list_workstreams(&WorkstreamIndex::Id, cursor.as_deref(), 1_000)
+            .await?;
+        workstreams.extend(page.items);
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(workstreams),
+        }
+    }
+}
@@ -1,18 +1,31 @@
-use auth::{AuthRequest, Authorization};
-use ethers::types::Address;
-use std::collections::HashMap;
-use std::str::FromStr;
-use users::User;
+use chain::RpcChain;
+use config::Config;
+use http::ApiRequest;
+use routes::Api;
+use store::KvStore;
 use worker::*;
-use workstreams::{Application, Workstream, WorkstreamState};
-mod auth;
-mod users;
-mod utils;
-mod workstreams;
+pub mod auth;
+pub mod chain;
+pub mod config;
+pub mod cookies;
+pub mod currencies;
+pub mod dates;
+pub mod error;
+pub mod filters;
+pub mod funding;
+pub mod http;
+pub mod indexer;
+pub mod objects;
+pub mod openapi;
+pub mod pagination;
+pub mod rates;
+pub mod routes;
+pub mod store;
+pub mod utils;
+pub mod validation;
+pub mod workstreams;
 
 // TODO:
-// - Verification on drips configuration
-// - Verification between allowing the user to change the state of the workstream
 // - try to use upstream libraries
 
 /// Log a request to the API. Boilerplate function , useful for debugging purposes.
@@ -26,50 +39,161 @@ fn log_request(req: &Request) {
     );
 }
 
-/// Checks if the request has an authorization token and if that oken is authorized to access
-/// the particular resource. Although complex schemes can be used with the Authorization.resources
-/// vector, currently we don't use that.
-///
-/// The authorization scheme is very simple:
-///
-/// A token that is tied to an Address A, has root access to all resources under `/api/v1/users/A`.
-/// For example, they can create a new workstream, edit an old one or delete, because the
-/// `workstreams` resource is under the following path: `/api/v1/users/A/workstreams/`.
-async fn is_authorized(req: &Request, env: &Env, ctx: &RouteContext<()>) -> Result<bool> {
-    let token = auth::Authorization::parse_request(req).await?;
-    let auth = match auth::Authorization::get(env, token).await? {
-        Some(authorization) => authorization,
-        None => return Ok(false),
-    };
-    let addr = Address::from_str(ctx.param("user").unwrap())
-        .map_err(|_| worker::Error::from("Cannot parse address"))?;
-    console_log!("Authorization is tied with user: {}", addr);
-    Ok(addr == auth.address)
-}
-
-/// Parses a workstream::Request and returns a HashMap of the query strings.
-///
-/// `/api/v1/workstreams?state=funded` will result in a hasmap with the following key-value pair:
-/// "state":"funded".
-fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
-    let url = req.url()?;
-    let args: HashMap<String, String> = url.query_pairs().into_owned().collect();
-    Ok(args)
-}
-
 /// # API schema
 ///
+/// The OpenAPI 3 document of the API is served at `/api/v1/openapi.json`. It's generated from the
+/// types of the API (see `openapi::document`), so it's the reference for the schemas of the
+/// bodies. The sections below describe the behaviour of the routes.
+///
+/// ## Pagination
+///
+/// The collection endpoints (`/api/v1/users`, `/api/v1/workstreams`,
+/// `/api/v1/users/:user/workstreams` and `.../applications`) return a page of their items:
+///
+/// ```json
+/// { "items": [...], "next_cursor": "7b224f6666736574223a3130307d" }
+/// ```
+///
+/// They accept the following parameters as a query string:
+/// - `limit`: the maximum number of items in the page, between 1 and 1000 (100 by default)
+/// - `sort`: `created_at`, `starting_at` or `title`. Users can't be sorted, other than by address
+/// - `order`: `asc` (default) or `desc`
+/// - `cursor`: the `next_cursor` of the previous page, which is `null` on the last page
+///
+/// The cursor is opaque and must be used with the same `sort` and `order` as the page that it was
+/// returned with. Invalid parameters return a `400` error.
+///
+/// ## Dates
+///
+/// All the dates (`created_at`, `starting_at`, `ending_at`) are returned as RFC 3339 timestamps in
+/// UTC, with milliseconds: `2022-03-05T12:17:31.000Z`. Requests should send them in RFC 3339 as
+/// well, but plain dates (`2022-03-05`), RFC 2822 dates and the legacy
+/// `March 5, 2022 12:17:31 GMT` format are accepted too. `starting_at` and `ending_at` can't be
+/// in the past and `starting_at` must be before `ending_at`.
+///
+/// ## Chains
+///
+/// The API can run workstreams on several chains, e.g mainnet and an L2, which are set with the
+/// `SIWE_CHAIN_ID` variable of the worker as a comma separated list (`1,10`). Every chain needs a
+/// JSON-RPC node, set with `RPC_URL_{chain id}`, e.g `RPC_URL_10`, or with `RPC_URL` for the first
+/// chain of the list.
+///
+/// A workstream runs on the `chain_id` of its drips configuration, which is mainnet (`1`) if it's
+/// not set, and is paid in a currency that is registered on that chain (see
+/// `/api/v1/currencies`). The chain of a workstream can't be changed.
+///
+/// A session is tied to the chain of the EIP4361 message that it was created with. Creating a
+/// workstream, changing its drips configuration and accepting applications to it, which changes
+/// its receivers, require a session on the chain of the workstream. Otherwise, the request fails
+/// with a `403` error.
+///
+/// ## Funding
+///
+/// The funding of the workstreams is followed on-chain by an indexer, which runs on the cron
+/// triggers of the worker (`[triggers]` in `wrangler.toml`). It reads the `DripsUpdated`, `Given`
+/// and `Collected` events of the DripsHub of every currency (see `/api/v1/currencies`), from the
+/// `start_block` of the currency, and records the events that concern a workstream in its funding
+/// ledger. An `Open` workstream becomes `Funded` once its drips account matches its drips
+/// configuration and has funds, and a `Funded` workstream becomes `Finished` once its account runs
+/// out of funds. The indexer stays 12 blocks behind the head of every chain.
+///
+/// The current funding of a workstream is served at
+/// `/api/v1/users/:user/workstreams/:workstream/funding`, read from its DripsHub on every
+/// request.
+///
+/// ## Payment rates
+///
+/// The `payment_rate` of a receiver is an amount of the `payment_currency` per period, sent as a
+/// string like `"1500 DAI/month"` or `"0.5 DAI/day"`. The periods are `second`, `minute`,
+/// `hour`, `day`, `week`, `month` (30 days) and `year` (365 days). The amount is converted
+/// exactly to the smallest unit of the currency, so it can't have more decimals than the currency
+/// (see `/api/v1/currencies`), and it must be in the currency of the workstream. A bare number is
+/// read as an amount of the smallest unit of the currency per second, as before rates had a unit.
+///
+/// Rates are returned in both raw and human forms, with the amounts as decimal strings:
+///
+/// ```json
+/// {
+///     "amount": "1500000000000000000000",
+///     "period": "month",
+///     "amount_per_second": "578703703703703",
+///     "human": "1500 DAI/month"
+/// }
+/// ```
+///
+/// Drips streams a whole amount every second, so `amount_per_second`, which is the rate of the
+/// receiver in the drips account, is rounded down. A returned rate can be sent back as it is.
+///
+/// ## Errors
+///
+/// Errors are returned with the HTTP status that matches them and a JSON body:
+///
+/// ```json
+/// { "code": "validation_error", "message": "can't parse Workstream State", "field": "state" }
+/// ```
+///
+/// `code` is stable and is one of:
+/// - `validation_error` (`400`): `field` names the invalid field of the body, or the invalid
+///   parameter, when there's a single one, and is `null` otherwise
+/// - `unauthorized` (`401`), `forbidden` (`403`), `not_found` (`404`), `method_not_allowed` (`405`)
+/// - `conflict` (`409`): the request conflicts with the state of the resource
+/// - `precondition_failed` (`412`), `precondition_required` (`428`): see Concurrency
+/// - `upstream_error` (`502`): a service that the API depends on, like the chain, failed
+/// - `internal_error` (`500`)
+///
+/// `message` is meant for humans and can change at any time.
+///
+/// The workstreams and the applications are validated as a whole when they are created or edited,
+/// and the error lists every invalid field in `violations`:
+///
+/// ```json
+/// {
+///     "code": "validation_error",
+///     "message": "invalid payload: title: must not be empty, receivers[1].address: duplicate receiver",
+///     "field": null,
+///     "violations": [
+///         { "field": "title", "message": "must not be empty" },
+///         { "field": "receivers[1].address", "message": "duplicate receiver" }
+///     ]
+/// }
+/// ```
+///
+/// Titles can be at most 200 characters long, descriptions 20000 characters, and there can be at
+/// most 100 receivers, with a `payment_rate` of at least 1 of the smallest unit of the currency
+/// per second and at most 1 unit of the currency per second (e.g 1 DAI). The limits are set by the
+/// `MAX_TITLE_LENGTH`, `MAX_DESCRIPTION_LENGTH`, `MAX_RECEIVERS` and `MAX_PAYMENT_RATE` (in units
+/// of the currency per second) variables of the worker. A currency of the registry can set its
+/// own `max_payment_rate`.
+///
+/// ## Concurrency
+///
+/// Workstreams and applications have a `version`, which is incremented on every change. The
+/// responses that return a single workstream or application carry its version in the `ETag`
+/// header, e.g `ETag: "3"`.
+///
+/// Requests that edit (PUT) or delete (DELETE) a workstream or an application must send the
+/// `ETag` of the version that they were based on in the `If-Match` header. If the resource has
+/// changed since, the request fails with a `412` error and the client must read it again. A
+/// request without `If-Match` fails with a `428` error. State transitions, and the review of
+/// applications, check `If-Match` only if it's sent.
+///
+/// A workstream and its applications are written one change at a time, by a Durable Object.
+/// If another request changes them between the moment that a request reads them and the moment
+/// that it writes them, the request fails with a `409` error and can be retried. The listings are
+/// read from replicas that are updated after every change, so they can lag behind for a moment.
+///
 /// ## /api/v1/users
 ///
 /// The route accepts the following HTTP methods: GET
 ///
 /// ## GET
 ///
-/// It returns an array of the addresses of all the users:
-/// ```
-/// [
-/// "0xDFA1fEa9915EF18b1f2A752343b168cA9c9d97aB"
-/// ]
+/// It returns a page of the addresses of all the users:
+/// ```json
+/// {
+///     "items": ["0xdfa1fea9915ef18b1f2a752343b168ca9c9d97ab"],
+///     "next_cursor": null
+/// }
 /// ```
 ///
 /// ## /api/v1/workstreams
@@ -78,33 +202,60 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// ### GET
 ///
-/// It returns an array of all the workstreams. It accepts a filter based on the state as a query
-/// string. For example `/api/v1/workstreams?state=funded`. The accepted states are defined in
-/// the WorkstreamState enum.
+/// It returns a page of workstreams. It accepts the following filters as a query string:
+/// - `state`: one of the states defined in the WorkstreamState enum, e.g `?state=funded`
+/// - `wtype`: one of the types defined in the WorkstreamType enum, e.g `?wtype=grant`
+/// - `creator`: the address of the creator of the workstreams
+/// - `payment_currency`: the symbol of a currency, case insensitive, e.g `?payment_currency=dai`
+/// - `chain_id`: the chain that the workstreams run on, e.g `?chain_id=10`
+/// - `starting_after`, `starting_before`: a range of `starting_at`, e.g `?starting_after=2022-03-01`
+/// - `ending_after`, `ending_before`: a range of `ending_at`
+/// - `receiver`: an address that the workstreams stream funds to, e.g the address of the user
+/// - `q`: text that the `title` or the `description` contains, case insensitive
+///
+/// Dates are RFC 3339 timestamps (`2022-03-05T12:17:31Z`) or plain dates (`2022-03-05`). A
+/// workstream is listed if it matches all the filters. Unknown parameters and invalid values
+/// return a `400` error.
+///
+/// The workstreams are read from indexes that are maintained on every write, so the request
+/// doesn't have to read the workstreams of every user. Unless `sort` is set, the pages follow the
+/// order of the index, so a page can hold fewer workstreams than `limit` even if it's not the last
+/// one, when more than one filter is used. A sorted listing reads the whole index instead.
 ///
 /// Response example:
-/// ```
-/// [
-///    {
-///        "id": "e0173d95-37a6-4089-b127-9eceee95574b",
-///        "wtype": "Grant",
-///        "creator": "0xdfa1fea9915ef18b1f2a752343b168ca9c9d97ab",
-///        "created_at": "Wed Mar 02 2022 12:46:38 GMT+0000 (Coordinated Universal Time)",
-///        "starting_at": "March 5, 2022 12:17:31 GMT",
-///        "ending_at": "March 10, 2022 16:17:31 GMT",
-///        "description": "lorem ipsum",
-///        "receivers": [
-///            {
-///                "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b",
-///                "payment_rate": 150
-///            }
-///        ],
-///        "drips_acct": 0,
-///        "payment_currency": "Dai",
-///        "drips_hub": "0x0000000000000000000000000000000000000000",
-///        "state": "Open"
-///    }
-/// ]
+/// ```json
+/// {
+///    "items": [
+///        {
+///            "id": "e0173d95-37a6-4089-b127-9eceee95574b",
+///            "title": "Radicle Drips integration",
+///            "wtype": "Grant",
+///            "creator": "0xdfa1fea9915ef18b1f2a752343b168ca9c9d97ab",
+///            "created_at": "2022-03-02T12:46:38.474Z",
+///            "starting_at": "2022-03-05T12:17:31.000Z",
+///            "ending_at": "2022-03-10T16:17:31.000Z",
+///            "description": "lorem ipsum",
+///            "receivers": [
+///                {
+///                    "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b",
+///                    "payment_rate": {
+///                        "amount": "1500000000000000000000",
+///                        "period": "month",
+///                        "amount_per_second": "578703703703703",
+///                        "human": "1500 DAI/month"
+///                    }
+///                }
+///            ],
+///            "chain_id": 1,
+///            "drips_acct": 0,
+///            "payment_currency": "DAI",
+///            "drips_hub": "0x73043143e0a6418cc45d82d4505b096b802fd365",
+///            "state": "Open",
+///            "version": 1
+///        }
+///    ],
+///    "next_cursor": "AAAAAKgw5x8g6Ff9W..."
+/// }
 /// ```
 ///
 /// ### /api/v1/users/:user/workstreams/:worksteam/applications
@@ -121,13 +272,17 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// ### GET
 ///
-/// Returns an array of all Applications of the workstream with id = `:worktream`.
+/// Returns a page of the Applications of the workstream with id = `:worktream`.
 ///
 /// ### POST
 ///
-/// Creates a new Application for the workstream with id = `workstream`.
+/// Creates a new Application for the workstream with id = `workstream`. The application is paid
+/// in the `payment_currency` of the workstream.
+///
+/// Any authorized user can apply to a workstream. The applicant, and thus the `creator` of the
+/// Application, is the address that the authorization token is tied to, not `:user`.
 ///
-/// The application is stored at the KV store of the API.
+/// The application is stored together with its workstream, in the Durable Object of the workstream.
 ///
 /// The user must pass a json object in the body of the request with a schema that follows the
 /// fields in the Application struct. All fields that have the `default` decorator, can be omitted,
@@ -135,18 +290,17 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// For example:
 ///{
-///     "wtype": "Grant",
-///     "creator": "0xDFA1fEa9915EF18b1f2A752343b168cA9c9d97aB",
-///     "starting_at": "March 13, 2022 16:17:31 GMT",
-///     "ending_at": "March 10, 2022 16:17:31 GMT",
+///     "title": "I want to work on this",
+///     "starting_at": "2022-03-10T16:17:31Z",
+///     "ending_at": "2022-03-13T16:17:31Z",
 ///     "description": "NEW TEST ipsum",
-///     "payment_currency": "Dai",
+///     "payment_currency": "DAI",
 ///     "receivers": [
 ///         {
 ///             "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b",
-///             "payment_rate": 150
+///             "payment_rate": "1500 DAI/month"
 ///         }
-///     ],
+///     ]
 /// }
 ///
 /// ### PUT
@@ -157,6 +311,9 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 /// - id
 /// - created_at
 /// - creator
+/// - state
+///
+/// Only the applicant can edit an Application.
 ///
 /// ## `/api/v1/users/:user/workstreams/:workstream/applications/:application`
 ///
@@ -170,7 +327,26 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 ///### DELETE
 ///
-/// Delete the Application object with id = `:application` from the KV STORE.
+/// Withdraws the application, deleting the Application object with id = `:application` from the
+/// store. Only the applicant can withdraw an Application.
+///
+/// ## `/api/v1/users/:user/workstreams/:workstream/applications/:application/accept`
+/// ## `/api/v1/users/:user/workstreams/:workstream/applications/:application/reject`
+///
+/// HTTP Methods: POST
+///
+/// Required Authorization: POST
+///
+/// ### POST
+///
+/// Accepts or rejects the `Pending` Application with id = `:application`. Only the creator of the
+/// workstream (`:user`) can review its applications.
+///
+/// When an Application is accepted, its `receivers` are added to the `receivers` of the
+/// workstream's drips configuration. Applications can't be accepted to a `Finished` or `Cancelled`
+/// workstream.
+///
+/// Returns the reviewed Application.
 ///
 /// ## `/api/v1/users/:user/workstreams
 ///
@@ -180,14 +356,14 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// ### GET
 ///
-/// Returns all workstreams of the user `:user`.
+/// Returns a page of the workstreams of the user `:user`.
 ///
 /// ### POST
 ///
 /// Creates a new workstream based on the Workstream struct that is passed as a JSON object in the
 /// body of the request.
 ///
-/// The workstream is saved at the KV store of the worker
+/// The workstream is saved in a Durable Object of its own, together with its future applications.
 ///
 /// Not all fields of the Workstream must be supplied by the user, as some are populated by the
 /// API.
@@ -196,7 +372,7 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 /// - id
 /// - creator (with :user)
 /// - created_at
-/// - DripsHub
+/// - drips_hub, with the DripsHub of the `payment_currency` (see `/api/v1/currencies`)
 /// - state
 ///
 ///
@@ -210,6 +386,11 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 /// - creator
 /// - created_at
 /// - Dripshub
+/// - state
+///
+/// The `payment_currency` can't be changed either. If the drips configuration (`drips_acct`,
+/// `receivers`) changes, it must match the drips account of the creator on-chain, as reported by
+/// the DripsHub contract.
 ///
 /// ## `/api/v1/users/:user/workstreams/:workstream
 ///
@@ -223,7 +404,160 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// ### DELETE
 ///
-/// Deletes the workstream with id = `:workstream` from the KV store of the API.
+/// Deletes the workstream with id = `:workstream`, together with its applications.
+///
+/// ## `/api/v1/users/:user/workstreams/:workstream/state`
+///
+/// HTTP Methods: POST
+///
+/// Require Authorization: POST
+///
+/// ### POST
+///
+/// Moves the workstream with id = `:workstream` to a new state, passed as a JSON object in the
+/// body of the request:
+///
+/// ```json
+/// { "state": "Funded" }
+/// ```
+///
+/// The allowed transitions are `Open` -> `Funded` -> `Finished`, and `Open` or `Funded` ->
+/// `Cancelled`. A workstream can become `Funded` only if its drips configuration matches the
+/// drips account of the creator on-chain and the account has funds left.
+/// An illegal transition, or one whose preconditions are not met, returns a `409` error. If the
+/// DripsHub can't be read to check the preconditions, it returns a `502` error.
+///
+/// Returns the updated workstream.
+///
+/// ## `/api/v1/users/:user/workstreams/:workstream/funding`
+///
+/// HTTP Methods: GET
+///
+/// ### GET
+///
+/// Returns the funding of the workstream with id = `:workstream`, as it's read from its DripsHub
+/// at `as_of`: the `balance` that is left in its drips account, the `amount_per_second` that the
+/// account streams to all its receivers, the date at which the account runs out of funds
+/// (`ends_at`, `null` if it doesn't stream to anyone) and the amount that every receiver of the
+/// account has collected so far (`total_collected`). The amounts are decimal strings, in the
+/// smallest unit of the currency of the workstream:
+///
+/// ```json
+/// {
+///     "workstream_id": "b1ec5b0e-0b8a-4d3b-a6f0-2f2a5b3c8d11",
+///     "balance": "999985000",
+///     "amount_per_second": "150",
+///     "ends_at": "2022-06-08T05:37:46.000Z",
+///     "as_of": "2022-03-23T01:48:20.000Z",
+///     "receivers": [
+///         {
+///             "address": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+///             "amount_per_second": "100",
+///             "total_collected": "100000"
+///         },
+///         {
+///             "address": "0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc",
+///             "amount_per_second": "50",
+///             "total_collected": "0"
+///         }
+///     ]
+/// }
+/// ```
+///
+/// The receivers are the ones of the drips account on-chain, which can differ from the receivers
+/// of the workstream until its drips configuration is updated. The `total_collected` of a
+/// receiver is collected from all of its senders, including the drips accounts of other
+/// workstreams, as DripsHub doesn't tell the senders apart once the funds are collected. A
+/// workstream whose account has never been configured has a `balance` of `0` and no receivers.
+/// If the DripsHub can't be read, it returns a `502` error.
+///
+/// ## `/api/v1/workstreams/:workstream`
+///
+/// A workstream can also be reached by its id alone, without the address of its creator, which
+/// is looked up in the id index of the workstreams. The following routes are aliases of the
+/// routes with the same suffix under `/api/v1/users/:user/workstreams/:workstream` and accept the
+/// same methods, bodies and authorization:
+/// - `/api/v1/workstreams/:workstream`
+/// - `/api/v1/workstreams/:workstream/state`
+/// - `/api/v1/workstreams/:workstream/funding`
+/// - `/api/v1/workstreams/:workstream/applications`
+/// - `/api/v1/workstreams/:workstream/applications/:application`
+/// - `/api/v1/workstreams/:workstream/applications/:application/accept`
+/// - `/api/v1/workstreams/:workstream/applications/:application/reject`
+///
+/// If there is no workstream with id = `:workstream`, they return a `404` error.
+///
+/// ## `/api/v1/users/:user/sessions`
+///
+/// HTTP Methods: GET, DELETE
+///
+/// Required Authorization: GET, DELETE
+///
+/// ### GET
+///
+/// Returns an array of the active Authorizations of `:user`. The tokens themselves are not
+/// returned.
+///
+/// ### DELETE
+///
+/// Revokes all the active Authorizations of `:user`, including the one that is used to make the
+/// request.
+///
+/// ## /api/v1/logout
+///
+/// HTTP Methods: POST
+///
+/// Required Authorization: POST
+///
+/// Revokes the authorization token that is used to make the request and clears the `SIWE-AUTH`
+/// cookie.
+///
+/// ## /api/v1/currencies
+///
+/// HTTP Methods: GET
+///
+/// Required Authorization: None
+///
+/// Returns the currencies that workstreams can be paid in, ordered by chain and symbol:
+///
+/// ```json
+/// [
+///     {
+///         "symbol": "DAI",
+///         "address": "0x6b175474e89094c44da98b954eedeac495271d0f",
+///         "decimals": 18,
+///         "chain_id": 1,
+///         "drips_hub": "0x73043143e0a6418cc45d82d4505b096b802fd365",
+///         "start_block": 14000000,
+///         "max_payment_rate": null
+///     }
+/// ]
+/// ```
+///
+/// The `payment_currency` of a workstream or an application is the `symbol` of one of them, case
+/// insensitive, that is registered on the chain of the workstream. A workstream is streamed by
+/// the DripsHub of its currency. The currencies are stored in the `DRIPSHUBS` namespace, as JSON
+/// keyed by `{chain_id}/{symbol}`, and are managed by the operators of the API. The
+/// `max_payment_rate` of a currency, in units of the currency per second, overrides the
+/// `MAX_PAYMENT_RATE` of the worker.
+///
+/// ## /api/v1/openapi.json
+///
+/// HTTP Methods: GET
+///
+/// Required Authorization: None
+///
+/// Returns the OpenAPI 3 document of the API, as JSON.
+///
+/// ## /api/v1/nonce
+///
+/// HTTP Methods: GET
+///
+/// Required Authorization: None
+///
+/// Returns a new nonce as plain text. The nonce must be used as the `Nonce` of the next EIP4361
+/// message that is sent to `/api/v1/authorize`. It can only be used once and it expires after
+/// `NONCE_TTL` seconds (10 minutes by default).
 ///
 /// ## /api/v1/authorize
 ///
@@ -239,19 +573,35 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// The message and signature **must** comform to EIP4361: https://eips.ethereum.org/EIPS/eip-4361
 ///
+/// The message must be issued for the domain and URI that the API is configured with
+/// (`SIWE_DOMAIN`, `SIWE_URI`), and for one of its chains (`SIWE_CHAIN_ID`), and its nonce must
+/// be one that was returned by `/api/v1/nonce` and has not been used before. Otherwise, the
+/// request fails with a `401` error.
+///
 /// The can be easily generated using:
 /// - [siwe-js](https://github.com/spruceid/siwe)
 /// - [siwe-rs](https://github.com/spruceid/siwe-rs)
 ///
 /// A succesful response will include the following cookie in the headers: `SIWE-AUTH=XXXXXX`,
-/// where XXXXX is the authorization token.
+/// where XXXXX is the authorization token. The cookie expires together with the token, at the
+/// `Expiration Time` of the message. Its `Domain` and `SameSite` attributes can be configured with
+/// the `COOKIE_DOMAIN` and `COOKIE_SAMESITE` environment variables.
 ///
 /// With that token, the user can authorize a request to access a resource via a method that
-/// requires authorization. The token expires automatically based on the AuthRequest object that
-/// was sent and must be renewed using the same mechanism.
+/// requires authorization. Browsers send the token back automatically in the `Cookie` header.
+/// Other clients can send it in the `Authorization` header instead:
+/// `Authorization: Bearer XXXXXX`. The token expires automatically based on the AuthRequest
+/// object that was sent and must be renewed using the same mechanism. It can also be revoked
+/// earlier, with `/api/v1/logout` or `/api/v1/users/:user/sessions`.
+///
+/// The token can't be used before the `Not Before` or after the `Expiration Time` of the message.
+/// If the message lists `Resources`, the token can only access the URLs that are equal to, or
+/// nested under, one of them. For example, a token with the resource
+/// `https://api.example.com/api/v1/users/0xab03..4/workstreams/e0173d95-37a6-4089-b127-9eceee95574b`
+/// can only edit that workstream and its applications.
 ///
 /// An example flow of the API:
-/// ```
+/// ```text
 ///      ┌─────────┐                                              ┌───┐                             ┌────────┐
 ///      │0xab03..4│                                              │API│                             │KV_STORE│
 ///      └────┬────┘                                              └─┬─┘                             └───┬────┘
@@ -281,326 +631,52 @@ fn parse_query_string(req: &Request) -> Result<HashMap<String, String>> {
 ///
 /// AuthRequest serialized in JSON:
 ///
-/// ```
+/// ```text
 /// '{\n    \"signature\": \"0x49a6e2a1995fde3bd10bd9ae2ecefe199ecfcb576125cc8582ee8458a4efd62668539b11f7bdb10e07f94b223f266cdd5ed592b37db4a2941541336a696d820a1c\",\n    \"message\": \"localhost:4361 wants you to sign in with your Ethereum account:\\n0xDFA1fEa9915EF18b1f2A752343b168cA9c9d97aB\\n\\nSIWE Notepad Example\\n\\nURI: http://localhost:4361\\nVersion: 1\\nChain ID: 1\\nNonce: zPPtgK5pMVHnnr8Co\\nIssued At: 2022-03-02T10:56:48.478Z\\nExpiration Time: 2022-03-02T20:56:48.474Z\\nResources:\\n- http://localhost:4361/address/0xDFA1fEa9915EF18b1f2A752343b168cA9c9d97aB\"\n}'
 /// ```
 ///
 /// If the authorization is succesful, the response will have the following header where the
 /// `SIWE-AUTH` cookie is the authorization token.
 ///
-/// ```
+/// ```text
 /// "set-cookie": "SIWE-AUTH=EACB9E10D0FD122CF0D2BA5F282CEBA0D71B48DD40A04893AAB94D1BE3F16F7D;
-/// Secure; HttpOnly; SameSite=Lax; Expires=Tue Mar 08 2022 20:51:45 GMT+0000 (Coordinated
-/// Universal Time)"
+/// Path=/; Secure; HttpOnly; SameSite=Lax; Expires=Wed, 02 Mar 2022 20:56:48 GMT; Max-Age=35999"
 /// ```
 ///
 ///
 ///
 ///
 #[event(fetch, respond_with_errors)]
-pub async fn main(req: Request, env: Env, _worker_ctx: Context) -> Result<Response> {
+pub async fn main(mut req: Request, env: Env, _worker_ctx: Context) -> Result<Response> {
     log_request(&req);
     utils::set_panic_hook();
-    let router = Router::new();
-    router
-        .get_async("/api/v1/users", |_req, ctx| async move {
-            let store = ctx.kv("USERS")?;
-            let users: Vec<String> = store
-                .list()
-                .execute()
-                .await?
-                .keys
-                .iter()
-                .map(|x| x.name.clone())
-                .collect();
-            Response::from_json(&users)
-        })
-        .get_async("/api/v1/workstreams", |req, ctx| async move {
-            let store = ctx.kv("USERS")?;
-            let args = parse_query_string(&req)?;
-            let addresses: Vec<String> = store
-                .list()
-                .execute()
-                .await?
-                .keys
-                .iter()
-                .map(|x| x.name.clone())
-                .collect();
-            let workstream_state: Option<WorkstreamState> = if let Some(state) = args.get("state") {
-                Some(WorkstreamState::from_str(state)?)
-            } else {
-                None
-            };
-            let mut workstreams: Vec<Workstream> = vec![];
-            for address in addresses {
-                let user = store.get(&address).json::<User>().await?.unwrap();
-                workstreams.extend(
-                    user.workstreams
-                        .into_values()
-                        .filter(|x| {
-                            if let Some(state) = &workstream_state {
-                                &x.state == state
-                            } else {
-                                true
-                            }
-                        })
-                        .collect::<Vec<Workstream>>(),
-                );
-            }
-            Response::from_json(&workstreams)
-        })
-        .on_async(
-            "/api/v1/users/:user/workstreams/:workstream/applications",
-            |mut req, ctx| async move {
-                let workstream_id = ctx.param("workstream").unwrap();
-                let user_address = ctx.param("user").unwrap();
-                console_log!(
-                    "user {} requested applications from workstream {} with method: {:?}",
-                    user_address,
-                    workstream_id,
-                    req.method()
-                );
-                return match req.method() {
-                    Method::Post => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let store = ctx.kv("APPLICATIONS")?;
-                        let mut application = req.json::<Application>().await?;
-                        let mut applications = if let Some(applications) = store
-                            .get(workstream_id)
-                            .json::<HashMap<String, Application>>()
-                            .await?
-                        {
-                            applications
-                        } else {
-                            HashMap::new()
-                        };
-                        Application::populate(&mut application, user_address, workstream_id)?;
-                        applications.insert(application.id.clone(), application.clone());
-                        store.put(workstream_id, applications)?.execute().await?;
-                        Response::from_json::<Application>(&application)
-                    }
-                    Method::Put => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let store = ctx.kv("APPLICATIONS")?;
-                        let mut new_application = req.json::<Application>().await?;
-                        let mut applications = match store
-                            .get(workstream_id)
-                            .json::<HashMap<String, Application>>()
-                            .await?
-                        {
-                            Some(applications) => {
-                                match applications.get(&new_application.id) {
-                                    Some(old_application) => {
-                                        Application::update(old_application, &mut new_application)?;
-                                    }
-                                    None => {
-                                        Application::populate(
-                                            &mut new_application,
-                                            user_address,
-                                            workstream_id,
-                                        )?;
-                                    }
-                                }
-                                applications
-                            }
-                            None => {
-                                Application::populate(
-                                    &mut new_application,
-                                    user_address,
-                                    workstream_id,
-                                )?;
-                                HashMap::new()
-                            }
-                        };
-                        applications.insert(workstream_id.to_string(), new_application.clone());
-                        store.put(workstream_id, applications)?.execute().await?;
-                        Response::from_json::<Application>(&new_application)
-                    }
-                    Method::Get => {
-                        return match ctx
-                            .kv("APPLICATIONS")?
-                            .get(workstream_id)
-                            .json::<HashMap<String, Application>>()
-                            .await?
-                        {
-                            Some(applications) => {
-                                Response::from_json::<HashMap<String, Application>>(&applications)
-                            }
-                            None => Response::error("No applications found for workstream", 404),
-                        }
-                    }
-                    _ => Response::error("HTTP Method Not Allowed", 405),
-                };
-            },
-        )
-        .on_async(
-            "/api/v1/users/:user/workstreams/:workstream/applications/:application",
-            |req, ctx| async move {
-                let workstream_id = ctx.param("workstream").unwrap();
-                let application_id = ctx.param("application").unwrap();
-                match req.method() {
-                    Method::Get => {
-                        return match ctx
-                            .kv("APPLICATIONS")?
-                            .get(workstream_id)
-                            .json::<HashMap<String, Application>>()
-                            .await?
-                        {
-                            Some(applications) => match applications.get(application_id) {
-                                Some(application) => {
-                                    Response::from_json::<Application>(application)
-                                }
-                                None => Response::error("Application Not Found", 404),
-                            },
-                            None => {
-                                Response::error("Workstream not found or has no applications", 404)
-                            }
-                        }
-                    }
-                    Method::Delete => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let store = ctx.kv("APPLICATIONS")?;
-                        return match store
-                            .get(workstream_id)
-                            .json::<HashMap<String, Application>>()
-                            .await?
-                        {
-                            Some(mut applications) => {
-                                let res = Response::from_json(&applications.remove(application_id));
-                                store.put(workstream_id, &applications)?.execute().await?;
-                                res
-                            }
-                            None => Response::error("Application not found", 404),
-                        };
-                    }
-                    _ => Response::error("HTTP Method Not Alloawed", 405),
-                }
-            },
-        )
-        .on_async(
-            "/api/v1/users/:user/workstreams",
-            |mut req, ctx| async move {
-                let addr_string = ctx.param("user").unwrap();
-                return match req.method() {
-                    Method::Post => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let mut workstream = req.json::<Workstream>().await?;
-                        let workstream_id =
-                            Workstream::populate(&mut workstream, addr_string, &ctx.env).await?;
-                        console_log!("New Workstream: \n {:?}", workstream);
-                        let store = ctx.kv("USERS")?;
-                        let mut user =
-                            if let Some(user) = store.get(addr_string).json::<User>().await? {
-                                user
-                            } else {
-                                User {
-                                    workstreams: HashMap::new(),
-                                }
-                            };
-                        user.workstreams.insert(workstream_id, workstream.clone());
-                        store.put(addr_string, user)?.execute().await?;
-                        Response::from_json::<Workstream>(&workstream)
-                    }
-                    Method::Get => {
-                        return match ctx.kv("USERS")?.get(addr_string).json::<User>().await? {
-                            Some(user) => Response::from_json(&user.workstreams),
-                            None => Response::error("User not found", 404),
-                        };
-                    }
-                    _ => Response::error("HTTP Method Not Allowed", 405),
-                };
-            },
-        )
-        .on_async(
-            "/api/v1/users/:user/workstreams/:workstream",
-            |mut req, ctx| async move {
-                let workstream_id = ctx.param("workstream").unwrap();
-                let addr_string = ctx.param("user").unwrap();
-                console_log!("path: {}", req.path());
-                console_log!(
-                    "user {} requested workstream {} with method: {:?}",
-                    addr_string,
-                    workstream_id,
-                    req.method()
-                );
-                return match req.method() {
-                    Method::Put => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let workstream_new: Workstream = req.json::<Workstream>().await?;
-                        let store = ctx.kv("USERS")?;
-                        if let Some(mut user) = store.get(addr_string).json::<User>().await? {
-                            let workstream_old = user.workstreams.get_mut(workstream_id);
-                            console_log!(
-                                "Editing old workstream \n{:?} \n with:\n{:?}",
-                                workstream_old,
-                                workstream_new
-                            );
-                            match workstream_old {
-                                Some(wk) => {
-                                    Workstream::update(wk, workstream_new.clone())?;
-                                    wk
-                                }
-                                None => {
-                                    return Response::error("Unknown workstream ID", 404);
-                                }
-                            };
-                            store.put(addr_string, user)?.execute().await?;
-                            return Response::ok("workstream updated");
-                        }
-                        Response::ok("workstream updated")
-                    }
-                    Method::Get => {
-                        return match ctx.kv("USERS")?.get(addr_string).json::<User>().await? {
-                            Some(user) => Response::from_json(&user.workstreams.get(workstream_id)),
-                            None => Response::error("Workstream not found", 404),
-                        };
-                    }
-                    Method::Delete => {
-                        if !is_authorized(&req, &ctx.env, &ctx).await? {
-                            return Response::error("Unauthorized", 401);
-                        }
-                        let store = ctx.kv("USERS")?;
-                        return match store.get(addr_string).json::<User>().await? {
-                            Some(mut user) => {
-                                let res =
-                                    Response::from_json(&user.workstreams.remove(workstream_id));
-                                store.put(addr_string, user)?.execute().await?;
-                                res
-                            }
-                            None => Response::error("User not found", 404),
-                        };
-                    }
-                    _ => Response::error("HTTP Method Not Allowed", 405),
-                };
-            },
-        )
-        .post_async("/api/v1/authorize", |req, ctx| async move {
-            let auth_req: AuthRequest = AuthRequest::from_req(req).await?;
-            let token: String = Authorization::create(&ctx.env, auth_req).await?;
-            let mut headers = Headers::new();
-            headers.set(
-                "Set-cookie",
-                &format!(
-                    "SIWE-AUTH={}; Secure; HttpOnly; SameSite=Lax; Expires={}",
-                    &token,
-                    Date::now().to_string()
-                ),
-            )?;
-            let res = Response::ok("authorization created")
-                .unwrap()
-                .with_headers(headers);
-            Ok(res)
-        })
-        .run(req, env)
-        .await
+    let store = KvStore::new(&env);
+    let config = Config::from_env(&env)?;
+    let chain = RpcChain::new(&config.rpc_urls)?;
+    let api = Api::new(&store, &chain, config);
+    let req = ApiRequest::from_worker(&mut req).await?;
+    routes::handle(&api, req).await?.into_worker()
+}
+
+/// Runs the indexer (see `indexer::run`) on the cron triggers of the worker, and sweeps the
+/// nonces that expired (see `KvStore::sweep_nonces`).
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+    if let Err(err) = KvStore::new(&env).backfill_users().await {
+        utils::log(&format!("backfill: {}", err));
+    }
+    if let Err(err) = KvStore::new(&env).sweep_nonces().await {
+        utils::log(&format!("nonces: {}", err));
+    }
+    if let Err(err) = index(&env).await {
+        utils::log(&format!("indexer: {}", err));
+    }
+}
+
+async fn index(env: &Env) -> Result<()> {
+    let store = KvStore::new(env);
+    let config = Config::from_env(env)?;
+    let chain = RpcChain::new(&config.rpc_urls)?;
+    indexer::run(&store, &chain, &config).await
 }
@@ -0,0 +1,319 @@
+use super::store::KvStore;
+use super::utils::log;
+use super::workstreams::{Application, Workstream};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use worker::wasm_bindgen::JsValue;
+use worker::*;
+
+/// The key under which a WorkstreamObject persists its workstream. The key is kept, with a `null`
+/// value, once the workstream is deleted, so that the object isn't seeded from the replicas again.
+const WORKSTREAM_KEY: &str = "workstream";
+
+/// The prefix of the keys under which a WorkstreamObject persists its applications, one per key,
+/// since a value of a Durable Object can't exceed 128 KiB.
+const APPLICATION_PREFIX: &str = "application/";
+
+fn application_key(id: &str) -> String {
+    format!("{}{}", APPLICATION_PREFIX, id)
+}
+
+/// A workstream together with its applications. It's the unit of consistency of the API: every
+/// change to a workstream or to one of its applications goes through the aggregate, which applies
+/// one command at a time.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkstreamAggregate {
+    pub workstream: Option<Workstream>,
+    /// application id => Application
+    pub applications: HashMap<String, Application>,
+}
+
+/// A command that is sent to the aggregate of a workstream.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Command {
+    /// Returns the aggregate without changing it.
+    Get,
+    /// Inserts or replaces the workstream and the applications, all or none. Every entity must
+    /// carry the version that follows the stored one (`1` for a new entity), so that a write that
+    /// was based on a stale read is rejected. Applications can only be put next to a workstream.
+    Put {
+        workstream: Option<Workstream>,
+        applications: Vec<Application>,
+    },
+    /// Deletes the workstream, together with its applications. Like a `Put`, it carries the
+    /// version that it was based on, which must be the stored one.
+    DeleteWorkstream {
+        version: u64,
+    },
+    DeleteApplication {
+        id: String,
+        version: u64,
+    },
+}
+
+/// The request that is sent to a WorkstreamObject. The object doesn't know the name that it was
+/// created with, so the id of the workstream is sent along with the command.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CommandRequest {
+    pub workstream_id: String,
+    pub command: Command,
+}
+
+/// The result of a command. `applied` is `false` if the command didn't change the aggregate:
+/// a `Put` or a delete that conflicts with the stored versions, a delete of something that
+/// doesn't exist, or a `Get`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Outcome {
+    pub applied: bool,
+    pub previous: WorkstreamAggregate,
+    pub current: WorkstreamAggregate,
+}
+
+impl WorkstreamAggregate {
+    /// Applies a command to the aggregate. This is the whole write logic of the aggregate, and it
+    /// runs both in the WorkstreamObject and, natively, in the MemoryStore.
+    pub fn apply(&mut self, command: Command) -> Outcome {
+        let previous = self.clone();
+        let applied = match command {
+            Command::Get => false,
+            Command::Put {
+                workstream,
+                applications,
+            } => self.put(workstream, applications),
+            Command::DeleteWorkstream { version } => {
+                if self.workstream.as_ref().map(|x| x.version) == Some(version) {
+                    self.applications.clear();
+                    self.workstream = None;
+                    true
+                } else {
+                    false
+                }
+            }
+            Command::DeleteApplication { id, version } => {
+                if self.applications.get(&id).map(|x| x.version) == Some(version) {
+                    self.applications.remove(&id);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        Outcome {
+            applied,
+            previous,
+            current: self.clone(),
+        }
+    }
+
+    fn put(&mut self, workstream: Option<Workstream>, applications: Vec<Application>) -> bool {
+        let follows = |stored: Option<u64>, version: u64| version == stored.unwrap_or(0) + 1;
+        if let Some(workstream) = &workstream {
+            if !follows(
+                self.workstream.as_ref().map(|x| x.version),
+                workstream.version,
+            ) {
+                return false;
+            }
+        } else if self.workstream.is_none() && !applications.is_empty() {
+            return false;
+        }
+        if applications.iter().any(|application| {
+            !follows(
+                self.applications.get(&application.id).map(|x| x.version),
+                application.version,
+            )
+        }) {
+            return false;
+        }
+        if workstream.is_some() {
+            self.workstream = workstream;
+        }
+        for application in applications {
+            self.applications
+                .insert(application.id.clone(), application);
+        }
+        true
+    }
+}
+
+/// A Durable Object that holds the aggregate of a single workstream, named after the id of the
+/// workstream. Durable Objects process one request at a time, so the commands are serialized
+/// and every write is checked against the latest state.
+///
+/// After every applied command, the object writes its whole aggregate to the KV namespaces, which
+/// are kept as read replicas for the listings (see `KvStore::replicate`). The command is already
+/// committed by then, so a replication that fails is only logged, and the replicas stay behind
+/// until the next write to the workstream, which replicates the aggregate again. An object that
+/// has never been written to is seeded from the replicas, so that the workstreams that were
+/// created before the objects can still be edited.
+#[durable_object]
+pub struct WorkstreamObject {
+    aggregate: Option<WorkstreamAggregate>,
+    /// Whether `aggregate` is persisted in the storage of the object, or was seeded from the
+    /// replicas and has to be written in full.
+    persisted: bool,
+    state: State,
+    env: Env,
+}
+
+impl WorkstreamObject {
+    /// Loads the aggregate from the storage of the object. It returns `None` if the object has
+    /// never been written to, and fails if the storage does.
+    async fn load(&self) -> Result<Option<WorkstreamAggregate>> {
+        let storage = self.state.storage();
+        let keys: Vec<String> = storage
+            .list()
+            .await?
+            .keys()
+            .into_iter()
+            .filter_map(|key| key.ok()?.as_string())
+            .collect();
+        if !keys.iter().any(|key| key == WORKSTREAM_KEY) {
+            return Ok(None);
+        }
+        let mut aggregate = WorkstreamAggregate {
+            workstream: storage.get(WORKSTREAM_KEY).await?,
+            applications: HashMap::new(),
+        };
+        for key in keys
+            .iter()
+            .filter(|key| key.starts_with(APPLICATION_PREFIX))
+        {
+            let application: Application = storage.get(key).await?;
+            aggregate
+                .applications
+                .insert(application.id.clone(), application);
+        }
+        Ok(Some(aggregate))
+    }
+
+    /// Writes the entities of `aggregate` that differ from `stored`, the aggregate as it's
+    /// persisted, or all of them if nothing is persisted yet.
+    async fn save(
+        &self,
+        stored: Option<&WorkstreamAggregate>,
+        aggregate: &WorkstreamAggregate,
+    ) -> Result<()> {
+        let mut storage = self.state.storage();
+        let empty = WorkstreamAggregate::default();
+        let mut entries: HashMap<String, serde_json::Value> = HashMap::new();
+        if stored.map(|x| &x.workstream) != Some(&aggregate.workstream) {
+            entries.insert(
+                WORKSTREAM_KEY.to_owned(),
+                serde_json::to_value(&aggregate.workstream)?,
+            );
+        }
+        let stored = stored.unwrap_or(&empty);
+        for (id, application) in &aggregate.applications {
+            if stored.applications.get(id) != Some(application) {
+                entries.insert(application_key(id), serde_json::to_value(application)?);
+            }
+        }
+        let deleted: Vec<String> = stored
+            .applications
+            .keys()
+            .filter(|id| !aggregate.applications.contains_key(*id))
+            .map(|id| application_key(id))
+            .collect();
+        if !entries.is_empty() {
+            storage.put_multiple(entries).await?;
+        }
+        if !deleted.is_empty() {
+            storage.delete_multiple(deleted).await?;
+        }
+        Ok(())
+    }
+}
+
+#[durable_object]
+impl DurableObject for WorkstreamObject {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            aggregate: None,
+            persisted: false,
+            state,
+            env,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let request = req.json::<CommandRequest>().await?;
+        let replicas = KvStore::new(&self.env);
+        let mut aggregate = match self.aggregate.take() {
+            Some(aggregate) => aggregate,
+            None => match self.load().await? {
+                Some(aggregate) => {
+                    self.persisted = true;
+                    aggregate
+                }
+                None => replicas.get_aggregate(&request.workstream_id).await?,
+            },
+        };
+        let outcome = aggregate.apply(request.command);
+        if outcome.applied {
+            let stored = Some(&outcome.previous).filter(|_| self.persisted);
+            self.save(stored, &aggregate).await?;
+            self.persisted = true;
+            if let Err(err) = replicas.replicate(&request.workstream_id, &outcome).await {
+                log(&format!(
+                    "replication of workstream {} failed: {}",
+                    request.workstream_id, err
+                ));
+            }
+        }
+        self.aggregate = Some(aggregate);
+        Response::from_json(&outcome)
+    }
+}
+
+/// The key under which a NonceObject persists the UNIX timestamp (in seconds) after which its
+/// nonce expires.
+const EXPIRES_AT_KEY: &str = "expires_at";
+
+/// A command that is sent to the object of a nonce.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NonceCommand {
+    /// Issues the nonce, until `expires_at`.
+    Put { expires_at: i64 },
+    /// Consumes the nonce, which deletes it from the storage of the object. The object responds
+    /// with `true` if the nonce was issued, hasn't expired and hasn't been consumed before.
+    Take,
+}
+
+/// A Durable Object that holds a single nonce, named after the nonce. A nonce is consumed by the
+/// same request that checks it, and the object processes one request at a time, so concurrent
+/// requests, even from different locations, can't consume the same nonce twice.
+#[durable_object]
+pub struct NonceObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for NonceObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let mut storage = self.state.storage();
+        match req.json::<NonceCommand>().await? {
+            NonceCommand::Put { expires_at } => {
+                storage.put(EXPIRES_AT_KEY, expires_at).await?;
+                Response::from_json(&true)
+            }
+            NonceCommand::Take => {
+                if !storage
+                    .list()
+                    .await?
+                    .has(&JsValue::from_str(EXPIRES_AT_KEY))
+                {
+                    return Response::from_json(&false);
+                }
+                let expires_at: i64 = storage.get(EXPIRES_AT_KEY).await?;
+                storage.delete(EXPIRES_AT_KEY).await?;
+                Response::from_json(&(expires_at > Utc::now().timestamp()))
+            }
+        }
+    }
+}
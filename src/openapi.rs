@@ -0,0 +1,303 @@
+use super::auth::{AuthRequest, Authorization};
+use super::currencies::Currency;
+use super::error::ErrorBody;
+use super::filters;
+use super::funding::Funding;
+use super::pagination::{Page, DEFAULT_LIMIT, MAX_LIMIT};
+use super::routes::{Route, ROUTES};
+use super::workstreams::{Application, StateTransition, Workstream};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// The path of a route pattern in the OpenAPI document, where the parameters are written as
+/// `{user}` instead of `:user`.
+pub fn path(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Generates the OpenAPI 3 document of the API, which is served at `/api/v1/openapi.json`.
+///
+/// The schemas of the bodies are derived from the types that the handlers deserialize and
+/// serialize (`Workstream`, `Application`, `AuthRequest`, ...) and every route of `ROUTES` has a
+/// path, so the document follows the code instead of drifting from it.
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for (pattern, route) in ROUTES {
+        let mut item = operations(*route, &mut gen);
+        let parameters: Vec<Value> = pattern
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .map(path_parameter)
+            .collect();
+        if !parameters.is_empty() {
+            item["parameters"] = json!(parameters);
+        }
+        paths.insert(path(pattern), item);
+    }
+    let error = schema::<ErrorBody>(&mut gen);
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "responses": {
+                "Error": {
+                    "description": "The request failed. Clients should switch on `code`.",
+                    "content": { "application/json": { "schema": error } },
+                },
+            },
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "cookie": { "type": "apiKey", "in": "cookie", "name": "SIWE-AUTH" },
+            },
+        },
+    })
+}
+
+/// The operations of a route, by HTTP method. The match is exhaustive, so that a new route
+/// can't be added without documenting it.
+fn operations(route: Route, gen: &mut SchemaGenerator) -> Value {
+    match route {
+        Route::Users => json!({
+            "get": operation("Lists the addresses of the users", false)
+                .query(pagination())
+                .returns(json_content(schema::<Page<String>>(gen))),
+        }),
+        Route::Workstreams => json!({
+            "get": operation("Lists and filters the workstreams", false)
+                .query(pagination())
+                .query(filters())
+                .returns(json_content(schema::<Page<Workstream>>(gen))),
+        }),
+        Route::Applications => json!({
+            "get": operation("Lists the applications to a workstream", false)
+                .query(pagination())
+                .returns(json_content(schema::<Page<Application>>(gen))),
+            "post": operation("Applies to a workstream", true)
+                .body(schema::<Application>(gen))
+                .returns(versioned(schema::<Application>(gen))),
+            "put": operation("Edits an application", true)
+                .if_match(true)
+                .body(schema::<Application>(gen))
+                .returns(versioned(schema::<Application>(gen))),
+        }),
+        Route::Application => json!({
+            "get": operation("Returns an application", false)
+                .returns(versioned(schema::<Application>(gen))),
+            "delete": operation("Withdraws an application", true)
+                .if_match(true)
+                .returns(json_content(schema::<Application>(gen))),
+        }),
+        Route::AcceptApplication => json!({
+            "post": operation("Accepts a pending application", true)
+                .if_match(false)
+                .returns(versioned(schema::<Application>(gen))),
+        }),
+        Route::RejectApplication => json!({
+            "post": operation("Rejects a pending application", true)
+                .if_match(false)
+                .returns(versioned(schema::<Application>(gen))),
+        }),
+        Route::UserWorkstreams => json!({
+            "get": operation("Lists the workstreams of a user", false)
+                .query(pagination())
+                .returns(json_content(schema::<Page<Workstream>>(gen))),
+            "post": operation("Creates a workstream", true)
+                .body(schema::<Workstream>(gen))
+                .returns(versioned(schema::<Workstream>(gen))),
+        }),
+        Route::UserWorkstream => json!({
+            "get": operation("Returns a workstream", false)
+                .returns(versioned(schema::<Workstream>(gen))),
+            "put": operation("Edits a workstream", true)
+                .if_match(true)
+                .body(schema::<Workstream>(gen))
+                .returns(text()),
+            "delete": operation("Deletes a workstream and its applications", true)
+                .if_match(true)
+                .returns(json_content(schema::<Workstream>(gen))),
+        }),
+        Route::Transition => json!({
+            "post": operation("Moves a workstream to a new state", true)
+                .if_match(false)
+                .body(schema::<StateTransition>(gen))
+                .returns(versioned(schema::<Workstream>(gen))),
+        }),
+        Route::Funding => json!({
+            "get": operation("Returns the funding of a workstream, read from its DripsHub", false)
+                .returns(json_content(schema::<Funding>(gen))),
+        }),
+        Route::Sessions => json!({
+            "get": operation("Lists the active authorizations of a user", true)
+                .returns(json_content(schema::<Vec<Authorization>>(gen))),
+            "delete": operation("Revokes all the authorizations of a user", true)
+                .returns(text()),
+        }),
+        Route::Nonce => json!({
+            "get": operation("Issues a nonce for the next EIP-4361 message", false)
+                .returns(text()),
+        }),
+        Route::Authorize => json!({
+            "post": operation("Exchanges a signed EIP-4361 message for a token", false)
+                .body(schema::<AuthRequest>(gen))
+                .returns(json!({
+                    "description": "The token is set in the `SIWE-AUTH` cookie",
+                    "headers": { "Set-Cookie": { "schema": { "type": "string" } } },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                })),
+        }),
+        Route::Logout => json!({
+            "post": operation("Revokes the token of the request", true).returns(text()),
+        }),
+        Route::Currencies => json!({
+            "get": operation("Lists the currencies that workstreams can be paid in", false)
+                .returns(json_content(schema::<Vec<Currency>>(gen))),
+        }),
+        Route::OpenApi => json!({
+            "get": operation("Returns this document", false)
+                .returns(json_content(json!({ "type": "object" }))),
+        }),
+    }
+}
+
+/// The schema of `T`, as a reference to `#/components/schemas` for the structs and enums.
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap_or_default()
+}
+
+/// An OpenAPI operation, built with the methods below.
+#[derive(Serialize)]
+#[serde(transparent)]
+struct Operation(Value);
+
+fn operation(summary: &str, authorized: bool) -> Operation {
+    let mut operation = json!({
+        "summary": summary,
+        "parameters": [],
+        "responses": { "default": { "$ref": "#/components/responses/Error" } },
+    });
+    if authorized {
+        operation["security"] = json!([{ "bearer": [] }, { "cookie": [] }]);
+    }
+    Operation(operation)
+}
+
+impl Operation {
+    fn query(mut self, parameters: Vec<Value>) -> Operation {
+        if let Some(existing) = self.0["parameters"].as_array_mut() {
+            existing.extend(parameters);
+        }
+        self
+    }
+
+    /// The `If-Match` header, with the `ETag` of the version that the request is based on.
+    fn if_match(self, required: bool) -> Operation {
+        self.query(vec![json!({
+            "name": "If-Match",
+            "in": "header",
+            "required": required,
+            "schema": { "type": "string" },
+        })])
+    }
+
+    fn body(mut self, schema: Value) -> Operation {
+        self.0["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self
+    }
+
+    fn returns(mut self, response: Value) -> Operation {
+        self.0["responses"]["200"] = response;
+        self
+    }
+}
+
+fn path_parameter(name: &str) -> Value {
+    let description = match name {
+        "user" => "The address of the user",
+        "workstream" => "The id of the workstream",
+        "application" => "The id of the application",
+        _ => "",
+    };
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn query_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "schema": schema })
+}
+
+/// The query string parameters of the listings (see `Pagination`).
+fn pagination() -> Vec<Value> {
+    vec![
+        query_parameter(
+            "limit",
+            json!({
+                "type": "integer",
+                "minimum": 1,
+                "maximum": MAX_LIMIT,
+                "default": DEFAULT_LIMIT,
+            }),
+        ),
+        query_parameter(
+            "sort",
+            json!({ "type": "string", "enum": ["created_at", "starting_at", "title"] }),
+        ),
+        query_parameter(
+            "order",
+            json!({ "type": "string", "enum": ["asc", "desc"], "default": "asc" }),
+        ),
+        query_parameter("cursor", json!({ "type": "string" })),
+    ]
+}
+
+/// The query string parameters that filter the workstream listing (see `WorkstreamFilter`).
+fn filters() -> Vec<Value> {
+    filters::PARAMETERS
+        .iter()
+        .map(|name| query_parameter(name, json!({ "type": "string" })))
+        .collect()
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({
+        "description": "OK",
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// A single workstream or application, whose version is returned in the `ETag` header.
+fn versioned(schema: Value) -> Value {
+    let mut response = json_content(schema);
+    response["headers"] = json!({ "ETag": { "schema": { "type": "string" } } });
+    response
+}
+
+fn text() -> Value {
+    json!({
+        "description": "OK",
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
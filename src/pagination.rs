@@ -0,0 +1,215 @@
+use super::error::{ApiError, ApiResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of items in a page, unless the request sets `limit`.
+pub const DEFAULT_LIMIT: usize = 100;
+/// The largest page that can be requested. It's also the largest page that KV can list at once.
+pub const MAX_LIMIT: usize = 1000;
+
+/// The query string parameters of the pagination.
+pub const PARAMETERS: &[&str] = &["limit", "sort", "order", "cursor"];
+
+/// The fields that a listing can be sorted by, with `?sort=`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    CreatedAt,
+    StartingAt,
+    Title,
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortKey::CreatedAt => write!(f, "created_at"),
+            SortKey::StartingAt => write!(f, "starting_at"),
+            SortKey::Title => write!(f, "title"),
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = ApiError;
+    fn from_str(input: &str) -> ApiResult<Self> {
+        match input {
+            "created_at" => Ok(SortKey::CreatedAt),
+            "starting_at" => Ok(SortKey::StartingAt),
+            "title" => Ok(SortKey::Title),
+            _ => Err(ApiError::invalid("sort", "can't parse sort")),
+        }
+    }
+}
+
+/// The direction of a sorted listing, with `?order=`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Asc
+    }
+}
+
+impl FromStr for Order {
+    type Err = ApiError;
+    fn from_str(input: &str) -> ApiResult<Self> {
+        match input.to_lowercase().as_ref() {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(ApiError::invalid("order", "can't parse order")),
+        }
+    }
+}
+
+/// The position of a page in a listing. It's handed to the clients as an opaque string, so that
+/// it can change without breaking them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cursor {
+    /// The number of items of a sorted listing that precede the page.
+    Offset(usize),
+    /// A cursor of the store, for listings that are read in the order of an index.
+    Store(String),
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> ApiResult<Cursor> {
+        hex::decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(invalid_cursor)
+    }
+}
+
+/// A page of a listing. `next_cursor` is `None` on the last page. Otherwise, it must be passed
+/// back as `cursor` in order to get the next page.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// The items of a listing that can be sorted.
+pub trait Sortable {
+    /// The keys that the items can be sorted by.
+    const SORT_KEYS: &'static [SortKey];
+    /// A unique key of the item. Items are ordered by it when no `sort` is requested, or when
+    /// their sort values are equal.
+    fn id(&self) -> &str;
+    /// The value of the item for one of `SORT_KEYS`. Items without a value come first.
+    fn sort_value(&self, key: SortKey) -> Option<String>;
+}
+
+impl Sortable for String {
+    const SORT_KEYS: &'static [SortKey] = &[];
+
+    fn id(&self) -> &str {
+        self
+    }
+
+    fn sort_value(&self, _key: SortKey) -> Option<String> {
+        None
+    }
+}
+
+/// The pagination parameters of a request to a collection endpoint:
+/// `?limit=&sort=created_at|starting_at|title&order=asc|desc&cursor=`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pagination {
+    pub limit: usize,
+    pub sort: Option<SortKey>,
+    pub order: Order,
+    pub cursor: Option<Cursor>,
+}
+
+impl Pagination {
+    /// Parses the pagination parameters out of the query string of a request, as returned by
+    /// `parse_query_string`. Other parameters are ignored.
+    pub fn from_query(query: &HashMap<String, String>) -> ApiResult<Pagination> {
+        let limit = match query.get("limit") {
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => limit,
+                _ => {
+                    return Err(ApiError::invalid(
+                        "limit",
+                        format!("limit must be between 1 and {}", MAX_LIMIT),
+                    ))
+                }
+            },
+            None => DEFAULT_LIMIT,
+        };
+        Ok(Pagination {
+            limit,
+            sort: query
+                .get("sort")
+                .map(|x| SortKey::from_str(x))
+                .transpose()?,
+            order: query
+                .get("order")
+                .map(|x| Order::from_str(x))
+                .transpose()?
+                .unwrap_or_default(),
+            cursor: query.get("cursor").map(|x| Cursor::decode(x)).transpose()?,
+        })
+    }
+
+    /// Returns the cursor of the store that the page starts at, for listings that are read from
+    /// an index of the store.
+    pub fn store_cursor(&self) -> ApiResult<Option<&str>> {
+        match &self.cursor {
+            None => Ok(None),
+            Some(Cursor::Store(cursor)) => Ok(Some(cursor)),
+            Some(Cursor::Offset(_)) => Err(invalid_cursor()),
+        }
+    }
+
+    /// Sorts a whole listing and returns the page that the cursor points to.
+    pub fn paginate<T: Sortable>(&self, mut items: Vec<T>) -> ApiResult<Page<T>> {
+        if let Some(key) = self.sort {
+            if !T::SORT_KEYS.contains(&key) {
+                return Err(ApiError::invalid("sort", format!("can't sort by {}", key)));
+            }
+        }
+        let offset = match &self.cursor {
+            None => 0,
+            Some(Cursor::Offset(offset)) => *offset,
+            Some(Cursor::Store(_)) => return Err(invalid_cursor()),
+        };
+        items.sort_by(|a, b| {
+            let ordering = match self.sort {
+                Some(key) => a.sort_value(key).cmp(&b.sort_value(key)),
+                None => Ordering::Equal,
+            }
+            .then_with(|| a.id().cmp(b.id()));
+            match self.order {
+                Order::Asc => ordering,
+                Order::Desc => ordering.reverse(),
+            }
+        });
+        let total = items.len();
+        let items: Vec<T> = items.into_iter().skip(offset).take(self.limit).collect();
+        let next = offset + items.len();
+        Ok(Page {
+            next_cursor: if next < total {
+                Some(Cursor::Offset(next).encode())
+            } else {
+                None
+            },
+            items,
+        })
+    }
+}
+
+fn invalid_cursor() -> ApiError {
+    ApiError::invalid("cursor", "invalid cursor")
+}
@@ -0,0 +1,221 @@
+use super::currencies::Currency;
+use ethers::types::U256;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The periods that a payment rate can be expressed in. A month is 30 days and a year is 365
+/// days, so that every period is a whole number of seconds.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    pub fn seconds(self) -> u64 {
+        match self {
+            Period::Second => 1,
+            Period::Minute => 60,
+            Period::Hour => 3_600,
+            Period::Day => 86_400,
+            Period::Week => 7 * 86_400,
+            Period::Month => 30 * 86_400,
+            Period::Year => 365 * 86_400,
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Period::Second => "second",
+            Period::Minute => "minute",
+            Period::Hour => "hour",
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Self, String> {
+        let lower = input.trim().to_lowercase();
+        match lower.strip_suffix('s').unwrap_or(lower.as_str()) {
+            "second" | "sec" => Ok(Period::Second),
+            "minute" | "min" => Ok(Period::Minute),
+            "hour" => Ok(Period::Hour),
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            "year" => Ok(Period::Year),
+            _ => Err(format!(
+                "unknown period `{}`, must be second, minute, hour, day, week, month or year",
+                input.trim()
+            )),
+        }
+    }
+}
+
+/// A payment rate, resolved against the currency of its workstream: `amount` of the smallest
+/// unit of the currency (e.g wei) every `period`.
+///
+/// Drips streams a whole amount every second, so `amount_per_second` is `amount` divided by the
+/// seconds of the period, rounded down. It's the amount that the drips account must stream to
+/// the receiver. The amounts are decimal strings, as they don't fit in the numbers of JSON.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct Rate {
+    #[serde(with = "decimal")]
+    #[schemars(with = "String")]
+    pub amount: U256,
+    pub period: Period,
+    /// Computed from `amount` and `period`, it's ignored in requests.
+    #[serde(default, with = "decimal")]
+    #[schemars(with = "String")]
+    pub amount_per_second: U256,
+    /// The rate in the units of the currency, e.g `1500 DAI/month`. It's ignored in requests.
+    #[serde(default)]
+    pub human: String,
+}
+
+impl Rate {
+    pub fn new(amount: U256, period: Period, currency: &Currency) -> Rate {
+        Rate {
+            amount,
+            period,
+            amount_per_second: amount / U256::from(period.seconds()),
+            human: format!(
+                "{} {}/{}",
+                format_units(amount, currency.decimals),
+                currency.symbol,
+                period
+            ),
+        }
+    }
+}
+
+/// The `payment_rate` of a receiver. Requests can send it in any of these forms, and it's
+/// resolved against the currency of the workstream (see `PaymentRate::resolve()`) before it's
+/// stored, so the API always returns a `Resolved` rate.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PaymentRate {
+    Resolved(Rate),
+    /// An amount of the currency per period, e.g `1500 DAI/month` or `0.5 DAI/day`.
+    Human(String),
+    /// An amount of the smallest unit of the currency per second, which is how the rates were
+    /// sent and stored before they had a unit.
+    PerSecond(u64),
+}
+
+impl PaymentRate {
+    /// Resolves the rate against `currency`. The amounts are converted exactly: a rate can't have
+    /// more decimals than the currency, and it must be in the currency.
+    ///
+    /// The `amount_per_second` and `human` of a `Resolved` rate are computed again, so that a
+    /// client can't send a rate that doesn't match its amount.
+    pub fn resolve(&self, currency: &Currency) -> Result<Rate, String> {
+        match self {
+            PaymentRate::Resolved(rate) => Ok(Rate::new(rate.amount, rate.period, currency)),
+            PaymentRate::Human(rate) => parse(rate, currency),
+            PaymentRate::PerSecond(amount) => {
+                Ok(Rate::new(U256::from(*amount), Period::Second, currency))
+            }
+        }
+    }
+
+    /// The amount that the rate streams every second. Rates are resolved before they're stored,
+    /// so it's zero only for a rate of a request that wasn't resolved yet.
+    pub fn amount_per_second(&self) -> U256 {
+        match self {
+            PaymentRate::Resolved(rate) => rate.amount_per_second,
+            PaymentRate::Human(_) => U256::zero(),
+            PaymentRate::PerSecond(amount) => U256::from(*amount),
+        }
+    }
+}
+
+/// Parses a rate like `1500 DAI/month` in `currency`.
+fn parse(rate: &str, currency: &Currency) -> Result<Rate, String> {
+    let usage = || format!("must be like `1500 {}/month`", currency.symbol);
+    let (amount, period) = rate.split_once('/').ok_or_else(usage)?;
+    let period = Period::from_str(period)?;
+    let (value, symbol) = match amount.split_whitespace().collect::<Vec<&str>>()[..] {
+        [value, symbol] => (value, symbol),
+        _ => return Err(usage()),
+    };
+    if !symbol.eq_ignore_ascii_case(currency.symbol.symbol()) {
+        return Err(format!("must be in {}", currency.symbol));
+    }
+    let amount = parse_units(value, currency.decimals)?;
+    Ok(Rate::new(amount, period, currency))
+}
+
+/// Parses a decimal amount of a currency with `decimals` into its smallest unit, exactly: `1.5`
+/// of a currency with 18 decimals is `1500000000000000000`.
+pub fn parse_units(value: &str, decimals: u8) -> Result<U256, String> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|x| x.is_ascii_digit())
+    {
+        return Err(format!("`{}` is not a decimal amount", value));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!("must have at most {} decimals", decimals));
+    }
+    let digits = format!(
+        "{}{:0<width$}",
+        integer,
+        fraction,
+        width = decimals as usize
+    );
+    U256::from_dec_str(&digits).map_err(|_| format!("`{}` is too large", value))
+}
+
+/// Formats an amount in the smallest unit of a currency with `decimals` as a decimal amount of
+/// the currency, without trailing zeros: `1500000000000000000` with 18 decimals is `1.5`.
+pub fn format_units(amount: U256, decimals: u8) -> String {
+    let digits = format!(
+        "{:0>width$}",
+        amount.to_string(),
+        width = decimals as usize + 1
+    );
+    let (integer, fraction) = digits.split_at(digits.len() - decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_owned()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+/// Serializes a `U256` as a decimal string. Use it with `#[serde(with = "decimal")]`.
+pub mod decimal {
+    use ethers::types::U256;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&amount.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        U256::from_dec_str(&amount)
+            .map_err(|_| D::Error::custom(format!("invalid amount: {}", amount)))
+    }
+}
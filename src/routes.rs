@@ -0,0 +1,840 @@
+use super::auth::{self, AuthRequest, Authorization};
+use super::chain::Chain;
+use super::config::Config;
+use super::cookies;
+use super::currencies::{self, Currency};
+use super::error::{ApiError, ApiResult};
+use super::filters::WorkstreamFilter;
+use super::funding::Funding;
+use super::http::{ApiRequest, ApiResponse};
+use super::openapi;
+use super::pagination::{Cursor, Pagination, Sortable, MAX_LIMIT};
+use super::store::Store;
+use super::utils::log;
+use super::workstreams::{
+    Application, ApplicationState, StateTransition, Workstream, WorkstreamState,
+};
+use chrono::Utc;
+use ethers::types::Address;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use worker::{Method, Result};
+
+/// Everything that a route handler needs in order to serve a request.
+pub struct Api<'a> {
+    pub store: &'a dyn Store,
+    pub chain: &'a dyn Chain,
+    pub config: Config,
+}
+
+impl<'a> Api<'a> {
+    pub fn new(store: &'a dyn Store, chain: &'a dyn Chain, config: Config) -> Api<'a> {
+        Api {
+            store,
+            chain,
+            config,
+        }
+    }
+}
+
+/// The routes of the API. Every route is served by the handler with the same name, except for
+/// `AcceptApplication` and `RejectApplication` which are both served by `review`.
+///
+/// The routes of a workstream can be reached both under its creator
+/// (`/api/v1/users/:user/workstreams/:workstream`) and by its id alone
+/// (`/api/v1/workstreams/:workstream`). In the latter case, the `:user` parameter is resolved
+/// from the id index of the workstreams before the request is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Users,
+    Workstreams,
+    Applications,
+    Application,
+    AcceptApplication,
+    RejectApplication,
+    UserWorkstreams,
+    UserWorkstream,
+    Transition,
+    Funding,
+    Sessions,
+    Nonce,
+    Authorize,
+    Logout,
+    OpenApi,
+    Currencies,
+}
+
+/// The path patterns of the routes. Segments that start with `:` are parameters and match any
+/// value, which is then available to the handler via `ApiRequest::param`.
+pub const ROUTES: &[(&str, Route)] = &[
+    ("/api/v1/users", Route::Users),
+    ("/api/v1/workstreams", Route::Workstreams),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/applications",
+        Route::Applications,
+    ),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/applications/:application",
+        Route::Application,
+    ),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/applications/:application/accept",
+        Route::AcceptApplication,
+    ),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/applications/:application/reject",
+        Route::RejectApplication,
+    ),
+    ("/api/v1/users/:user/workstreams", Route::UserWorkstreams),
+    (
+        "/api/v1/users/:user/workstreams/:workstream",
+        Route::UserWorkstream,
+    ),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/state",
+        Route::Transition,
+    ),
+    (
+        "/api/v1/users/:user/workstreams/:workstream/funding",
+        Route::Funding,
+    ),
+    ("/api/v1/workstreams/:workstream", Route::UserWorkstream),
+    ("/api/v1/workstreams/:workstream/state", Route::Transition),
+    ("/api/v1/workstreams/:workstream/funding", Route::Funding),
+    (
+        "/api/v1/workstreams/:workstream/applications",
+        Route::Applications,
+    ),
+    (
+        "/api/v1/workstreams/:workstream/applications/:application",
+        Route::Application,
+    ),
+    (
+        "/api/v1/workstreams/:workstream/applications/:application/accept",
+        Route::AcceptApplication,
+    ),
+    (
+        "/api/v1/workstreams/:workstream/applications/:application/reject",
+        Route::RejectApplication,
+    ),
+    ("/api/v1/users/:user/sessions", Route::Sessions),
+    ("/api/v1/nonce", Route::Nonce),
+    ("/api/v1/authorize", Route::Authorize),
+    ("/api/v1/logout", Route::Logout),
+    ("/api/v1/openapi.json", Route::OpenApi),
+    ("/api/v1/currencies", Route::Currencies),
+];
+
+/// Matches a path against a route pattern and returns the parameters of the path if it matches.
+fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    if pattern.len() != path.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (expected, actual) in pattern.iter().zip(path.iter()) {
+        if let Some(name) = expected.strip_prefix(':') {
+            if actual.is_empty() {
+                return None;
+            }
+            params.insert(name.to_owned(), (*actual).to_owned());
+        } else if expected != actual {
+            return None;
+        }
+    }
+    Some(params)
+}
+
+/// Routes a request to the handler of the first route that matches its path. The errors of the
+/// handlers are turned into their responses (see `ApiError`), so that they never reach the
+/// runtime as `500`s.
+pub async fn handle(api: &Api<'_>, req: ApiRequest) -> Result<ApiResponse> {
+    Ok(route(api, req).await.unwrap_or_else(|err| {
+        if err.status() >= 500 {
+            log(&format!("request failed: {}", err));
+        }
+        err.into_response()
+    }))
+}
+
+async fn route(api: &Api<'_>, mut req: ApiRequest) -> ApiResult<ApiResponse> {
+    let path = req.path().to_owned();
+    for (pattern, route) in ROUTES {
+        if let Some(mut params) = match_route(pattern, &path) {
+            if let (Some(id), None) = (params.get("workstream"), params.get("user")) {
+                match api.store.find_workstream(id).await? {
+                    Some(workstream) => {
+                        params.insert("user".to_owned(), format!("{:?}", workstream.creator));
+                    }
+                    None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+                }
+            }
+            req.params = params;
+            return match route {
+                Route::Users => users(api, req).await,
+                Route::Workstreams => workstreams(api, req).await,
+                Route::Applications => applications(api, req).await,
+                Route::Application => application(api, req).await,
+                Route::AcceptApplication => review(api, req, ApplicationState::Accepted).await,
+                Route::RejectApplication => review(api, req, ApplicationState::Rejected).await,
+                Route::UserWorkstreams => user_workstreams(api, req).await,
+                Route::UserWorkstream => user_workstream(api, req).await,
+                Route::Transition => transition(api, req).await,
+                Route::Funding => funding(api, req).await,
+                Route::Sessions => sessions(api, req).await,
+                Route::Nonce => nonce(api, req).await,
+                Route::Authorize => authorize(api, req).await,
+                Route::Logout => logout(api, req).await,
+                Route::OpenApi => open_api(req),
+                Route::Currencies => currencies(api, req).await,
+            };
+        }
+    }
+    Err(ApiError::NotFound("Not Found".into()))
+}
+
+/// Checks if the request has an authorization token and if that oken is authorized to access
+/// the particular resource.
+///
+/// The authorization scheme is very simple:
+///
+/// A token that is tied to an Address A, has root access to all resources under `/api/v1/users/A`.
+/// For example, they can create a new workstream, edit an old one or delete, because the
+/// `workstreams` resource is under the following path: `/api/v1/users/A/workstreams/`.
+///
+/// If the token was issued with `resources`, it can only access the URLs under them (see
+/// `Authorization::grants()`). That way, a user can sign a token that can only touch one
+/// workstream.
+async fn is_authorized(api: &Api<'_>, req: &ApiRequest) -> ApiResult<bool> {
+    Ok(session(api, req).await?.is_some())
+}
+
+/// Returns the authorization of the request, if it's authorized to access the resources of
+/// `:user` (see `is_authorized`).
+async fn session(api: &Api<'_>, req: &ApiRequest) -> ApiResult<Option<Authorization>> {
+    let authorization = match authorization(api, req).await? {
+        Some(authorization) => authorization,
+        None => return Ok(None),
+    };
+    let addr = parse_address(param(req, "user")?)?;
+    log(&format!(
+        "Authorization is tied with user: {}",
+        authorization.address
+    ));
+    Ok(Some(authorization).filter(|x| x.address == addr))
+}
+
+/// Checks that the session of a request that changes the funding details of a workstream was
+/// signed in on the chain of the workstream, so that a session on one chain can't point a
+/// workstream to the drips of another.
+fn check_chain(session: &Authorization, workstream: &Workstream) -> ApiResult<()> {
+    if session.chain_id != workstream.chain_id() {
+        return Err(ApiError::Forbidden(format!(
+            "The session is on chain {}, but the workstream is on chain {}",
+            session.chain_id,
+            workstream.chain_id()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the address that the authorization token of the request is tied to, or `None` if the
+/// request doesn't carry a valid token. It's used by the resources that are not owned by the
+/// `:user` of the path, like the applications to a workstream, which are owned by the applicant.
+///
+/// A token is valid only between the `not_before` and `expiration_time` of its Authorization and
+/// only for the URLs that its `resources` grant access to.
+async fn authenticate(api: &Api<'_>, req: &ApiRequest) -> ApiResult<Option<Address>> {
+    Ok(authorization(api, req).await?.map(|auth| auth.address))
+}
+
+/// Returns the authorization of the token of the request, if the token is valid (see
+/// `authenticate`).
+async fn authorization(api: &Api<'_>, req: &ApiRequest) -> ApiResult<Option<Authorization>> {
+    let token = match Authorization::parse_request(req) {
+        Ok(token) => token,
+        Err(_) => return Ok(None),
+    };
+    Ok(Authorization::get(api.store, token)
+        .await?
+        .filter(|auth| auth.is_active(Utc::now()) && auth.grants(&req.url)))
+}
+
+/// Parses an ApiRequest and returns a HashMap of the query strings.
+///
+/// `/api/v1/workstreams?state=funded` will result in a hasmap with the following key-value pair:
+/// "state":"funded".
+fn parse_query_string(req: &ApiRequest) -> HashMap<String, String> {
+    req.url.query_pairs().into_owned().collect()
+}
+
+/// Parses the pagination parameters of the query string of a request (see `Pagination`).
+fn parse_pagination(req: &ApiRequest) -> ApiResult<Pagination> {
+    Pagination::from_query(&parse_query_string(req))
+}
+
+/// Responds with the page of a whole listing that the request asks for, sorted as requested.
+/// Invalid pagination parameters return a `400` error.
+fn page_response<T: Sortable + Serialize>(
+    req: &ApiRequest,
+    items: Vec<T>,
+) -> ApiResult<ApiResponse> {
+    ApiResponse::from_json(&parse_pagination(req)?.paginate(items)?)
+}
+
+/// The `ETag` of a version of a workstream or an application.
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Responds with a workstream or an application, along with the `ETag` of its version.
+fn versioned<T: Serialize>(resource: &T, version: u64) -> ApiResult<ApiResponse> {
+    Ok(ApiResponse::from_json(resource)?.with_header("ETag", &etag(version)))
+}
+
+/// Checks the `If-Match` header of a request that changes a resource, which is at `version`. It
+/// fails with a `412` error if the header doesn't match the current `ETag`, so that a client
+/// can't overwrite a change that it hasn't seen, or with a `428` error if the header is
+/// `required` and missing.
+fn check_if_match(req: &ApiRequest, version: u64, required: bool) -> ApiResult<()> {
+    let header = match req.header("If-Match") {
+        Some(header) => header,
+        None if required => {
+            return Err(ApiError::PreconditionRequired(
+                "If-Match header is required".into(),
+            ));
+        }
+        None => return Ok(()),
+    };
+    let current = etag(version);
+    if header
+        .split(',')
+        .map(|x| x.trim().trim_start_matches("W/"))
+        .any(|x| x == "*" || x == current)
+    {
+        Ok(())
+    } else {
+        Err(ApiError::PreconditionFailed(
+            "The resource has been modified since it was read".into(),
+        ))
+    }
+}
+
+/// Responds to a write that lost a race with another write of the same workstream, after the
+/// request read it: the aggregate of the workstream only accepts writes that are based on its
+/// latest version.
+fn conflict() -> ApiError {
+    ApiError::Conflict(
+        "The resource has been modified by another request, read it and try again".into(),
+    )
+}
+
+fn param<'r>(req: &'r ApiRequest, name: &str) -> ApiResult<&'r str> {
+    req.param(name)
+        .map(|x| x.as_str())
+        .ok_or_else(|| ApiError::Internal(format!("missing path parameter: {}", name)))
+}
+
+/// Parses the `:user` parameter of a path.
+fn parse_address(address: &str) -> ApiResult<Address> {
+    Address::from_str(address).map_err(|_| ApiError::invalid("user", "Cannot parse address"))
+}
+
+async fn users(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    match req.method {
+        Method::Get => {
+            let users: Vec<String> = api.store.list_users().await?;
+            page_response(&req, users)
+        }
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
+
+async fn workstreams(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Get {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    let filter = WorkstreamFilter::from_query(&parse_query_string(&req))?;
+    let pagination = parse_pagination(&req)?;
+    let index = filter.index();
+    if pagination.sort.is_some() {
+        // the indexes are ordered by id, so a sorted listing needs the whole index
+        let mut workstreams = vec![];
+        let mut cursor = None;
+        loop {
+            let page = api
+                .store
+                .list_workstreams(&index, cursor.as_deref(), MAX_LIMIT)
+                .await?;
+            workstreams.extend(page.items.into_iter().filter(|x| x.matches(&filter)));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        return page_response(&req, workstreams);
+    }
+    let cursor = pagination.store_cursor()?;
+    let mut page = api
+        .store
+        .list_workstreams(&index, cursor, pagination.limit)
+        .await?;
+    page.items.retain(|x| x.matches(&filter));
+    page.next_cursor = page.next_cursor.map(|x| Cursor::Store(x).encode());
+    ApiResponse::from_json(&page)
+}
+
+async fn applications(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    let workstream_id = param(&req, "workstream")?;
+    let user_address = param(&req, "user")?;
+    log(&format!(
+        "user {} requested applications from workstream {} with method: {:?}",
+        user_address, workstream_id, req.method
+    ));
+    match req.method {
+        Method::Post => {
+            let applicant = match authenticate(api, &req).await? {
+                Some(applicant) => applicant,
+                None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+            };
+            let currency = workstream_currency(api, user_address, workstream_id).await?;
+            let mut application = req.json::<Application>()?;
+            Application::populate(
+                &mut application,
+                &format!("{:?}", applicant),
+                workstream_id,
+                &currency,
+                &api.config.limits,
+            )?;
+            if !api.store.put_application(&application).await? {
+                return Err(ApiError::NotFound("Unknown workstream ID".into()));
+            }
+            versioned(&application, application.version)
+        }
+        Method::Put => {
+            let applicant = match authenticate(api, &req).await? {
+                Some(applicant) => applicant,
+                None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+            };
+            let mut new_application = req.json::<Application>()?;
+            let old_application = match api
+                .store
+                .get_application(workstream_id, &new_application.id)
+                .await?
+            {
+                Some(application) => application,
+                None => return Err(ApiError::NotFound("Application not found".into())),
+            };
+            if old_application.creator != applicant {
+                return Err(ApiError::Forbidden(
+                    "Only the applicant can edit an application".into(),
+                ));
+            }
+            check_if_match(&req, old_application.version, true)?;
+            let currency = workstream_currency(api, user_address, workstream_id).await?;
+            Application::update(
+                &old_application,
+                &mut new_application,
+                &currency,
+                &api.config.limits,
+            )?;
+            if !api.store.put_application(&new_application).await? {
+                return Err(conflict());
+            }
+            versioned(&new_application, new_application.version)
+        }
+        Method::Get => match api.store.list_applications(workstream_id).await? {
+            Some(applications) => page_response(&req, applications.into_values().collect()),
+            None => Err(ApiError::NotFound(
+                "No applications found for workstream".into(),
+            )),
+        },
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
+
+/// The currency of a workstream, which its applications are paid in.
+async fn workstream_currency(
+    api: &Api<'_>,
+    user: &str,
+    workstream_id: &str,
+) -> ApiResult<Currency> {
+    let workstream = match api
+        .store
+        .get_workstream(&parse_address(user)?, workstream_id)
+        .await?
+    {
+        Some(workstream) => workstream,
+        None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+    };
+    currencies::lookup(
+        api.store,
+        workstream.chain_id(),
+        workstream.payment_currency(),
+    )
+    .await
+}
+
+async fn application(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    let workstream_id = param(&req, "workstream")?;
+    let application_id = param(&req, "application")?;
+    match req.method {
+        Method::Get => match api.store.list_applications(workstream_id).await? {
+            Some(applications) => match applications.get(application_id) {
+                Some(application) => versioned(application, application.version),
+                None => Err(ApiError::NotFound("Application Not Found".into())),
+            },
+            None => Err(ApiError::NotFound(
+                "Workstream not found or has no applications".into(),
+            )),
+        },
+        Method::Delete => {
+            let applicant = match authenticate(api, &req).await? {
+                Some(applicant) => applicant,
+                None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+            };
+            let version = match api
+                .store
+                .get_application(workstream_id, application_id)
+                .await?
+            {
+                Some(application) if application.creator != applicant => {
+                    return Err(ApiError::Forbidden(
+                        "Only the applicant can withdraw an application".into(),
+                    ))
+                }
+                Some(application) => {
+                    check_if_match(&req, application.version, true)?;
+                    application.version
+                }
+                None => return Err(ApiError::NotFound("Application not found".into())),
+            };
+            match api
+                .store
+                .delete_application(workstream_id, application_id, version)
+                .await?
+            {
+                Some(application) => ApiResponse::from_json(&application),
+                None => Err(conflict()),
+            }
+        }
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
+
+/// Accepts or rejects an application. Only the creator of the workstream (`:user`) can review
+/// the applications to it, and only while they are `Pending`.
+async fn review(
+    api: &Api<'_>,
+    req: ApiRequest,
+    decision: ApplicationState,
+) -> ApiResult<ApiResponse> {
+    if req.method != Method::Post {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    let session = match session(api, &req).await? {
+        Some(session) => session,
+        None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+    };
+    let workstream_id = param(&req, "workstream")?;
+    let application_id = param(&req, "application")?;
+    let addr = parse_address(param(&req, "user")?)?;
+    let mut workstream = match api.store.get_workstream(&addr, workstream_id).await? {
+        Some(wk) => wk,
+        None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+    };
+    let mut application = match api
+        .store
+        .get_application(workstream_id, application_id)
+        .await?
+    {
+        Some(application) => application,
+        None => return Err(ApiError::NotFound("Application not found".into())),
+    };
+    check_if_match(&req, application.version, false)?;
+    if application.state != ApplicationState::Pending {
+        return Err(ApiError::Conflict(
+            "Application has already been reviewed".into(),
+        ));
+    }
+    match decision {
+        ApplicationState::Accepted => {
+            if matches!(
+                workstream.state,
+                WorkstreamState::Finished | WorkstreamState::Cancelled
+            ) {
+                return Err(ApiError::Conflict(format!(
+                    "Workstream is {}",
+                    workstream.state
+                )));
+            }
+            check_chain(&session, &workstream)?;
+            Application::accept(&mut application, &mut workstream, &api.config.limits)?;
+            if !api
+                .store
+                .accept_application(&workstream, &application)
+                .await?
+            {
+                return Err(conflict());
+            }
+        }
+        ApplicationState::Rejected => {
+            Application::reject(&mut application);
+            if !api.store.put_application(&application).await? {
+                return Err(conflict());
+            }
+        }
+        ApplicationState::Pending => {
+            return Err(ApiError::Conflict(
+                "Application can't be moved back to Pending".into(),
+            ))
+        }
+    }
+    versioned(&application, application.version)
+}
+
+async fn user_workstreams(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    let addr_string = param(&req, "user")?;
+    match req.method {
+        Method::Post => {
+            let session = match session(api, &req).await? {
+                Some(session) => session,
+                None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+            };
+            let mut workstream = req.json::<Workstream>()?;
+            check_chain(&session, &workstream)?;
+            Workstream::populate(&mut workstream, addr_string, api.store, &api.config.limits)
+                .await?;
+            log(&format!("New Workstream: \n {:?}", workstream));
+            if !api.store.put_workstream(&workstream).await? {
+                return Err(conflict());
+            }
+            versioned(&workstream, workstream.version)
+        }
+        Method::Get => {
+            let addr = parse_address(addr_string)?;
+            match api.store.list_user_workstreams(&addr).await? {
+                Some(workstreams) => page_response(&req, workstreams.into_values().collect()),
+                None => Err(ApiError::NotFound("User not found".into())),
+            }
+        }
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
+
+async fn user_workstream(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    let workstream_id = param(&req, "workstream")?;
+    let addr_string = param(&req, "user")?;
+    log(&format!(
+        "user {} requested workstream {} with method: {:?}",
+        addr_string, workstream_id, req.method
+    ));
+    let addr = parse_address(addr_string)?;
+    match req.method {
+        Method::Put => {
+            let session = match session(api, &req).await? {
+                Some(session) => session,
+                None => return Err(ApiError::Unauthorized("Unauthorized".into())),
+            };
+            let workstream_new: Workstream = req.json::<Workstream>()?;
+            let mut workstream_old = match api.store.get_workstream(&addr, workstream_id).await? {
+                Some(wk) => wk,
+                None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+            };
+            check_if_match(&req, workstream_old.version, true)?;
+            let currency = currencies::lookup(
+                api.store,
+                workstream_old.chain_id(),
+                workstream_old.payment_currency(),
+            )
+            .await?;
+            if workstream_old.changes_funding(&workstream_new, &currency) {
+                check_chain(&session, &workstream_old)?;
+            }
+            log(&format!(
+                "Editing old workstream \n{:?} \n with:\n{:?}",
+                workstream_old, workstream_new
+            ));
+            Workstream::update(
+                &mut workstream_old,
+                workstream_new,
+                &currency,
+                api.chain,
+                &api.config.limits,
+            )
+            .await?;
+            if !api.store.put_workstream(&workstream_old).await? {
+                return Err(conflict());
+            }
+            Ok(ApiResponse::ok("workstream updated")?
+                .with_header("ETag", &etag(workstream_old.version)))
+        }
+        Method::Get => match api.store.get_workstream(&addr, workstream_id).await? {
+            Some(workstream) => versioned(&workstream, workstream.version),
+            None => Err(ApiError::NotFound("Workstream not found".into())),
+        },
+        Method::Delete => {
+            if !is_authorized(api, &req).await? {
+                return Err(ApiError::Unauthorized("Unauthorized".into()));
+            }
+            let version = match api.store.get_workstream(&addr, workstream_id).await? {
+                Some(workstream) => {
+                    check_if_match(&req, workstream.version, true)?;
+                    workstream.version
+                }
+                None => return Err(ApiError::NotFound("Workstream not found".into())),
+            };
+            match api
+                .store
+                .delete_workstream(&addr, workstream_id, version)
+                .await?
+            {
+                Some(workstream) => ApiResponse::from_json(&workstream),
+                None => Err(conflict()),
+            }
+        }
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
+
+async fn transition(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Post {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    if !is_authorized(api, &req).await? {
+        return Err(ApiError::Unauthorized("Unauthorized".into()));
+    }
+    let workstream_id = param(&req, "workstream")?;
+    let addr = parse_address(param(&req, "user")?)?;
+    let transition = req.json::<StateTransition>()?;
+    let mut workstream = match api.store.get_workstream(&addr, workstream_id).await? {
+        Some(wk) => wk,
+        None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+    };
+    check_if_match(&req, workstream.version, false)?;
+    let currency = currencies::lookup(
+        api.store,
+        workstream.chain_id(),
+        workstream.payment_currency(),
+    )
+    .await?;
+    Workstream::transition(
+        &mut workstream,
+        transition.state,
+        currency.start_block,
+        api.chain,
+    )
+    .await?;
+    if !api.store.put_workstream(&workstream).await? {
+        return Err(conflict());
+    }
+    versioned(&workstream, workstream.version)
+}
+
+/// Returns the funding of a workstream, as it's read from its DripsHub.
+async fn funding(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Get {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    let workstream_id = param(&req, "workstream")?;
+    let addr = parse_address(param(&req, "user")?)?;
+    let workstream = match api.store.get_workstream(&addr, workstream_id).await? {
+        Some(wk) => wk,
+        None => return Err(ApiError::NotFound("Unknown workstream ID".into())),
+    };
+    let currency = currencies::lookup(
+        api.store,
+        workstream.chain_id(),
+        workstream.payment_currency(),
+    )
+    .await?;
+    let funding = Funding::of(&workstream, currency.start_block, api.chain)
+        .await
+        .map_err(ApiError::upstream)?;
+    ApiResponse::from_json(&funding)
+}
+
+async fn nonce(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Get {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    ApiResponse::ok(auth::issue_nonce(api.store, &api.config).await?)
+}
+
+async fn authorize(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Post {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    let auth_req: AuthRequest = AuthRequest::from_req(&req)?;
+    let (token, authorization) =
+        Authorization::create(api.store, api.chain, &api.config, auth_req).await?;
+    Ok(ApiResponse::ok("authorization created")?.with_header(
+        "Set-Cookie",
+        &cookies::session_cookie(&token, authorization.expires_at(), &api.config.cookies),
+    ))
+}
+
+/// Revokes the authorization token of the request.
+async fn logout(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Post {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    let token = match Authorization::parse_request(&req) {
+        Ok(token) => token,
+        Err(_) => return Err(ApiError::Unauthorized("Unauthorized".into())),
+    };
+    if api.store.get_authorization(&token).await?.is_none() {
+        return Err(ApiError::Unauthorized("Unauthorized".into()));
+    }
+    api.store.delete_authorization(&token).await?;
+    Ok(ApiResponse::ok("authorization revoked")?.with_header(
+        "Set-Cookie",
+        &cookies::expired_session_cookie(&api.config.cookies),
+    ))
+}
+
+/// Lists the currencies that workstreams can be paid in.
+async fn currencies(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Get {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    ApiResponse::from_json(&api.store.list_currencies().await?)
+}
+
+/// Returns the OpenAPI document of the API.
+fn open_api(req: ApiRequest) -> ApiResult<ApiResponse> {
+    if req.method != Method::Get {
+        return Err(ApiError::MethodNotAllowed);
+    }
+    ApiResponse::from_json(&openapi::document())
+}
+
+/// Lists or revokes all the active authorizations of `:user`.
+async fn sessions(api: &Api<'_>, req: ApiRequest) -> ApiResult<ApiResponse> {
+    if !is_authorized(api, &req).await? {
+        return Err(ApiError::Unauthorized("Unauthorized".into()));
+    }
+    let addr = parse_address(param(&req, "user")?)?;
+    match req.method {
+        Method::Get => {
+            let sessions: Vec<Authorization> = api
+                .store
+                .list_authorizations(&addr)
+                .await?
+                .into_iter()
+                .map(|(_, authorization)| authorization)
+                .collect();
+            ApiResponse::from_json(&sessions)
+        }
+        Method::Delete => {
+            let sessions = api.store.list_authorizations(&addr).await?;
+            for (token, _) in &sessions {
+                api.store.delete_authorization(token).await?;
+            }
+            ApiResponse::ok(format!("{} authorizations revoked", sessions.len()))
+        }
+        _ => Err(ApiError::MethodNotAllowed),
+    }
+}
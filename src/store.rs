@@ -0,0 +1,1165 @@
+use super::auth::Authorization;
+use super::currencies::Currency;
+use super::indexer::Ledger;
+use super::objects::{Command, CommandRequest, NonceCommand, Outcome, WorkstreamAggregate};
+use super::pagination::Page;
+use super::utils::log;
+use super::workstreams::{Application, PaymentCurrency, Workstream, WorkstreamState};
+use async_trait::async_trait;
+use chrono::Utc;
+use ethers::types::Address;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use worker::wasm_bindgen::JsValue;
+use worker::{Env, Error, Method, Request, RequestInit, Result};
+
+/// The secondary indexes of the workstreams, which are maintained on every write, so that the
+/// workstreams can be listed without reading the workstreams of every user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WorkstreamIndex {
+    /// All the workstreams, ordered by id.
+    Id,
+    State(WorkstreamState),
+    Creator(Address),
+    Currency(PaymentCurrency),
+}
+
+impl WorkstreamIndex {
+    /// The common prefix of the keys of the index.
+    fn prefix(&self) -> String {
+        match self {
+            WorkstreamIndex::Id => "id/".to_owned(),
+            WorkstreamIndex::State(state) => format!("state/{}/", state),
+            WorkstreamIndex::Creator(creator) => format!("creator/{:?}/", creator),
+            WorkstreamIndex::Currency(currency) => format!("currency/{}/", currency),
+        }
+    }
+}
+
+/// The storage backend of the API. The route handlers only talk to a `Store`, so that the
+/// workstream, application and authorization logic doesn't depend on the Cloudflare runtime.
+///
+/// A workstream and its applications form an aggregate (see `WorkstreamAggregate`), which is
+/// changed one command at a time, so concurrent writes can't overwrite each other. The listings
+/// are read from replicas of the aggregates, which are updated after every write.
+///
+/// Two implementations are provided:
+/// - [`KvStore`], which is used in production and is backed by Durable Objects and the
+///   Cloudflare KV namespaces
+/// - [`MemoryStore`], which keeps everything in memory and is used to run the API natively
+#[async_trait(?Send)]
+pub trait Store {
+    /// Returns the addresses of all the users that have at least one workstream, from the
+    /// `creator` index.
+    async fn list_users(&self) -> Result<Vec<String>>;
+    /// Returns a page of at most `limit` workstreams from an index, starting after `cursor`. The
+    /// `next_cursor` of the page is a cursor of the store, not of the API.
+    /// Indexes are eventually consistent, so a page can contain fewer workstreams, or
+    /// workstreams that have just stopped matching the index.
+    async fn list_workstreams(
+        &self,
+        index: &WorkstreamIndex,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Workstream>>;
+    /// Returns the workstreams of a single user, keyed by the workstream id, from the `creator`
+    /// index. It returns `None` if the user has no workstreams.
+    async fn list_user_workstreams(
+        &self,
+        creator: &Address,
+    ) -> Result<Option<HashMap<String, Workstream>>>;
+    /// Returns a workstream of `creator`, as it's stored in its aggregate.
+    async fn get_workstream(&self, creator: &Address, id: &str) -> Result<Option<Workstream>>;
+    /// Returns a workstream by its id alone, as it's stored in its aggregate.
+    async fn find_workstream(&self, id: &str) -> Result<Option<Workstream>>;
+    /// Inserts or replaces a workstream in its aggregate and updates the indexes of the
+    /// workstream. It returns `false`, without writing anything, if `workstream.version` doesn't
+    /// follow the stored version (see `Command::Put`).
+    async fn put_workstream(&self, workstream: &Workstream) -> Result<bool>;
+    /// Deletes a workstream of `creator`, together with its applications, and returns it. It
+    /// returns `None`, without deleting anything, if the workstream doesn't exist or its stored
+    /// version isn't `version` (see `Command::DeleteWorkstream`).
+    async fn delete_workstream(
+        &self,
+        creator: &Address,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Workstream>>;
+    /// Returns the applications of a workstream, keyed by the application id. It returns `None`
+    /// if the workstream has never received an application. The applications are read from a
+    /// replica of the aggregate, which can lag behind it.
+    async fn list_applications(
+        &self,
+        workstream_id: &str,
+    ) -> Result<Option<HashMap<String, Application>>>;
+    /// Returns an application, as it's stored in the aggregate of its workstream.
+    async fn get_application(&self, workstream_id: &str, id: &str) -> Result<Option<Application>>;
+    /// Inserts or replaces an application, in the aggregate of the workstream that is defined in
+    /// `application.workstream_id`. It returns `false`, without writing anything, if the
+    /// workstream doesn't exist or `application.version` doesn't follow the stored version.
+    async fn put_application(&self, application: &Application) -> Result<bool>;
+    /// Puts a workstream and one of its applications at once, as the acceptance of an
+    /// application changes both. It returns `false`, without writing anything, if either version
+    /// doesn't follow the stored one.
+    async fn accept_application(
+        &self,
+        workstream: &Workstream,
+        application: &Application,
+    ) -> Result<bool>;
+    /// Deletes an application and returns it. It returns `None`, without deleting anything, if
+    /// the application doesn't exist or its stored version isn't `version`.
+    async fn delete_application(
+        &self,
+        workstream_id: &str,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Application>>;
+    async fn get_authorization(&self, token: &str) -> Result<Option<Authorization>>;
+    /// Stores an authorization under `token`. If an `expiration` (UNIX timestamp in seconds) is
+    /// passed, the backend is expected to drop the authorization after that moment.
+    async fn put_authorization(
+        &self,
+        token: &str,
+        authorization: &Authorization,
+        expiration: Option<u64>,
+    ) -> Result<()>;
+    /// Deletes an authorization, so that its token can no longer be used.
+    async fn delete_authorization(&self, token: &str) -> Result<()>;
+    /// Returns the active authorizations of an address, along with their tokens.
+    async fn list_authorizations(&self, address: &Address) -> Result<Vec<(String, Authorization)>>;
+    /// Stores a nonce that can be used once, for the next `ttl` seconds.
+    async fn put_nonce(&self, nonce: &str, ttl: u64) -> Result<()>;
+    /// Consumes a nonce. It returns `false` if the nonce was never issued, has expired, or has
+    /// already been consumed.
+    async fn take_nonce(&self, nonce: &str) -> Result<bool>;
+    /// Returns the currency of the registry with the given symbol on `chain_id`, or `None` if
+    /// it's not supported on that chain.
+    async fn get_currency(
+        &self,
+        chain_id: u64,
+        symbol: &PaymentCurrency,
+    ) -> Result<Option<Currency>>;
+    async fn put_currency(&self, currency: &Currency) -> Result<()>;
+    /// Returns all the currencies of the registry, ordered by chain and symbol.
+    async fn list_currencies(&self) -> Result<Vec<Currency>>;
+    async fn delete_currency(&self, chain_id: u64, symbol: &PaymentCurrency) -> Result<()>;
+    /// Returns the funding ledger of a workstream, as it's recorded by the indexer, or `None` if
+    /// the indexer hasn't started it yet.
+    async fn get_ledger(&self, workstream_id: &str) -> Result<Option<Ledger>>;
+    async fn put_ledger(&self, ledger: &Ledger) -> Result<()>;
+    /// Returns the last block of `chain_id` that the indexer has read the events of `drips_hub`
+    /// up to, or `None` if it has never indexed the DripsHub.
+    async fn get_indexed_block(&self, chain_id: u64, drips_hub: &Address) -> Result<Option<u64>>;
+    async fn put_indexed_block(&self, chain_id: u64, drips_hub: &Address, block: u64)
+        -> Result<()>;
+}
+
+/// The key under which a copy of a workstream is stored in the `id` index.
+fn workstream_key(id: &str) -> String {
+    format!("{}{}", WorkstreamIndex::Id.prefix(), id)
+}
+
+/// The keys under which a workstream is listed in the `state`, `creator` and `currency` indexes.
+/// The value of every key is the id of the workstream, which is also the last segment of the key.
+fn index_keys(workstream: &Workstream) -> Vec<String> {
+    [
+        WorkstreamIndex::State(workstream.state),
+        WorkstreamIndex::Creator(workstream.creator),
+        WorkstreamIndex::Currency(workstream.payment_currency().clone()),
+    ]
+    .iter()
+    .map(|index| format!("{}{}", index.prefix(), workstream.id))
+    .collect()
+}
+
+/// The key under which a currency is stored in the registry. The same symbol can be registered on
+/// several chains, with a different ERC-20 and DripsHub on each.
+fn currency_key(chain_id: u64, symbol: &PaymentCurrency) -> String {
+    format!("{}/{}", chain_id, symbol)
+}
+
+/// Checks if a key of the `DRIPSHUBS` namespace is the key of a currency (see `currency_key`).
+fn is_currency_key(key: &str) -> bool {
+    match key.split_once('/') {
+        Some((chain_id, symbol)) => chain_id.parse::<u64>().is_ok() && !symbol.is_empty(),
+        None => false,
+    }
+}
+
+/// The key under which the ledger of a workstream is stored.
+fn ledger_key(workstream_id: &str) -> String {
+    format!("ledger/{}", workstream_id)
+}
+
+/// The key under which the indexer stores the last block that it has indexed, per DripsHub.
+fn indexed_block_key(chain_id: u64, drips_hub: &Address) -> String {
+    format!("indexer/{}/{:?}", chain_id, drips_hub)
+}
+
+/// The key under which the `USERS` backfill records that it has completed (see
+/// `KvStore::backfill_users`).
+const USERS_BACKFILL_KEY: &str = "migrations/users";
+
+/// The key under which the token of an authorization is indexed by the address it's tied to. The
+/// index entries expire together with the authorization, so listing the keys with the
+/// `session/{address}/` prefix returns the active tokens of an address.
+fn session_key(address: &Address, token: &str) -> String {
+    format!("session/{:?}/{}", address, token)
+}
+
+/// The key under which an issued nonce is indexed until its object is swept (see
+/// `KvStore::sweep_nonces`). The expiration is zero-padded, so the keys are listed in the order
+/// that the nonces expire.
+fn nonce_key(expires_at: i64, nonce: &str) -> String {
+    format!("nonce/{:012}/{}", expires_at, nonce)
+}
+
+/// The applications of a workstream, as they are stored in the `APPLICATIONS` namespace. The
+/// applications are flattened next to the version, so that the blobs that were written before
+/// the version was introduced can still be read.
+#[derive(Default, Serialize, Deserialize)]
+struct Applications {
+    /// Incremented on every write of the blob.
+    #[serde(default)]
+    version: u64,
+    #[serde(flatten)]
+    applications: HashMap<String, Application>,
+}
+
+/// The workstreams of a creator, as the first version of the API stored them in the `USERS`
+/// namespace.
+#[derive(Deserialize)]
+struct LegacyUser {
+    workstreams: HashMap<String, Workstream>,
+}
+
+/// Reads the workstreams of a creator from its blob in the `USERS` namespace. The workstreams
+/// take their id from their key in the blob, and their creator from the key of the blob.
+pub fn legacy_workstreams(address: &str, user: &str) -> Result<Vec<Workstream>> {
+    let user: LegacyUser = serde_json::from_str(user)?;
+    let mut workstreams: Vec<Workstream> = user
+        .workstreams
+        .into_iter()
+        .map(|(id, mut workstream)| {
+            workstream.id = id;
+            if let Ok(creator) = address.parse() {
+                workstream.creator = creator;
+            }
+            workstream
+        })
+        .collect();
+    workstreams.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(workstreams)
+}
+
+/// A Store backed by a Durable Object per workstream (`WORKSTREAM_OBJECTS`), which holds the
+/// aggregate of the workstream, a Durable Object per nonce (`NONCE_OBJECTS`), and the Cloudflare
+/// KV namespaces that are defined in `wrangler.toml`:
+/// - `APPLICATIONS`: workstream id => HashMap<application id, Application> and a `version`
+/// - `AUTHENTICATION`: token => Authorization, `session/{address}/{token}` => token,
+///   `nonce/{expires at}/{nonce}` => the nonces whose objects aren't swept yet
+/// - `DRIPSHUBS`: `{chain id}/{symbol}` => Currency, the registry of the supported currencies
+/// - `WORKSTREAMS`: `id/{id}` => Workstream, `state/{state}/{id}`, `creator/{address}/{id}`,
+///   `currency/{currency}/{id}` => id, and the state of the indexer: `ledger/{id}` => Ledger,
+///   `indexer/{chain id}/{DripsHub}` => the last indexed block
+/// - `USERS`: address => the workstreams of the address, as the first version of the API stored
+///   them. It's only read by `KvStore::backfill_users`.
+///
+/// The `APPLICATIONS` and `WORKSTREAMS` namespaces are read replicas of the aggregates, which are
+/// written by the objects (see `WorkstreamObject`) and only used for the listings.
+pub struct KvStore<'a> {
+    env: &'a Env,
+}
+
+impl<'a> KvStore<'a> {
+    pub fn new(env: &'a Env) -> KvStore<'a> {
+        KvStore { env }
+    }
+
+    /// Sends a command to the object of a workstream and returns its outcome.
+    async fn command(&self, workstream_id: &str, command: Command) -> Result<Outcome> {
+        let request = CommandRequest {
+            workstream_id: workstream_id.to_owned(),
+            command,
+        };
+        self.fetch_object("WORKSTREAM_OBJECTS", workstream_id, &request)
+            .await
+    }
+
+    /// Sends a command to the object of a nonce (see `NonceObject`).
+    async fn nonce_command(&self, nonce: &str, command: NonceCommand) -> Result<bool> {
+        self.fetch_object("NONCE_OBJECTS", nonce, &command).await
+    }
+
+    /// Sends `body` as JSON to the Durable Object of `binding` that is named `name`, and returns
+    /// its JSON response.
+    async fn fetch_object<T: Serialize, R: DeserializeOwned>(
+        &self,
+        binding: &str,
+        name: &str,
+        body: &T,
+    ) -> Result<R> {
+        let body = serde_json::to_string(body).map_err(|err| Error::from(err.to_string()))?;
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_body(Some(JsValue::from_str(&body)));
+        let req = Request::new_with_init("https://durable-object/", &init)?;
+        self.env
+            .durable_object(binding)?
+            .id_from_name(name)?
+            .get_stub()?
+            .fetch_with_request(req)
+            .await?
+            .json::<R>()
+            .await
+    }
+
+    /// Reads the aggregate of a workstream from the replicas. It's used to seed the objects that
+    /// have never been written to.
+    pub async fn get_aggregate(&self, workstream_id: &str) -> Result<WorkstreamAggregate> {
+        Ok(WorkstreamAggregate {
+            workstream: self.get_replica(workstream_id).await?,
+            applications: self
+                .get_applications(workstream_id)
+                .await?
+                .map(|blob| blob.applications)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Updates the replicas of an aggregate after a command has been applied to it. The whole
+    /// aggregate is replicated, not only what the command changed, and the indexes are
+    /// reconciled with the stored replica, so replicas that missed a write catch up with the
+    /// next one.
+    pub async fn replicate(&self, workstream_id: &str, outcome: &Outcome) -> Result<()> {
+        let current = &outcome.current;
+        let workstream = match &current.workstream {
+            Some(workstream) => workstream,
+            None => {
+                if let Some(replica) = self.get_replica(workstream_id).await? {
+                    self.unindex_workstream(&replica).await?;
+                }
+                return self
+                    .env
+                    .kv("APPLICATIONS")?
+                    .delete(workstream_id)
+                    .await
+                    .map_err(Error::from);
+            }
+        };
+        self.index_workstream(workstream).await?;
+        let mut blob = self
+            .get_applications(workstream_id)
+            .await?
+            .unwrap_or_default();
+        blob.applications = current.applications.clone();
+        self.put_applications(workstream_id, &mut blob).await
+    }
+
+    /// Copies the workstreams of the `USERS` namespace into the replicas and the indexes of the
+    /// `WORKSTREAMS` namespace, from which their objects are then seeded. Until then, the
+    /// workstreams that were created before the indexes are neither listed nor found.
+    ///
+    /// It runs on the cron triggers of the worker until it has copied every user: the workstreams
+    /// that are already replicated are skipped, and a user that can't be read is logged and
+    /// tried again on the next run.
+    pub async fn backfill_users(&self) -> Result<()> {
+        let store = self.env.kv("WORKSTREAMS")?;
+        if store.get(USERS_BACKFILL_KEY).text().await?.is_some() {
+            return Ok(());
+        }
+        let users = self.env.kv("USERS")?;
+        let mut complete = true;
+        for address in self.list_keys("USERS", "").await? {
+            let workstreams = users
+                .get(&address)
+                .text()
+                .await
+                .map_err(Error::from)
+                .and_then(|user| {
+                    user.map(|user| legacy_workstreams(&address, &user))
+                        .transpose()
+                });
+            let workstreams = match workstreams {
+                Ok(Some(workstreams)) => workstreams,
+                Ok(None) => continue,
+                Err(err) => {
+                    log(&format!(
+                        "backfill: user {} can't be read: {:?}",
+                        address, err
+                    ));
+                    complete = false;
+                    continue;
+                }
+            };
+            for workstream in workstreams {
+                if self.get_replica(&workstream.id).await?.is_some() {
+                    continue;
+                }
+                self.index_workstream(&workstream).await?;
+            }
+        }
+        if complete {
+            store
+                .put(USERS_BACKFILL_KEY, Utc::now().to_rfc3339())?
+                .execute()
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the objects of the nonces that have expired, so that the nonces that are issued
+    /// but never used don't stay in storage. A nonce that is used is deleted by its object right
+    /// away, and taking it again here is a no-op.
+    ///
+    /// It runs on the cron triggers of the worker. A nonce whose object can't be reached stays in
+    /// the index and is swept on the next run.
+    pub async fn sweep_nonces(&self) -> Result<()> {
+        let store = self.env.kv("AUTHENTICATION")?;
+        let now = Utc::now().timestamp();
+        for key in self.list_keys("AUTHENTICATION", "nonce/").await? {
+            let (expires_at, nonce) = match key.trim_start_matches("nonce/").split_once('/') {
+                Some((expires_at, nonce)) => (expires_at.parse::<i64>().unwrap_or(0), nonce),
+                None => continue,
+            };
+            // the keys are ordered by expiration
+            if expires_at > now {
+                break;
+            }
+            self.nonce_command(nonce, NonceCommand::Take).await?;
+            store.delete(&key).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the names of all the keys of a namespace that start with `prefix`, following the
+    /// pagination cursors of KV.
+    async fn list_keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let store = self.env.kv(namespace)?;
+        let mut names = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut list = store.list().prefix(prefix.to_owned());
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let page = list.execute().await?;
+            names.extend(page.keys.into_iter().map(|key| key.name));
+            if page.list_complete {
+                return Ok(names);
+            }
+            cursor = page.cursor;
+        }
+    }
+
+    /// Returns the copy of a workstream that is stored in the `id` index.
+    async fn get_replica(&self, id: &str) -> Result<Option<Workstream>> {
+        self.env
+            .kv("WORKSTREAMS")?
+            .get(&workstream_key(id))
+            .json::<Workstream>()
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Updates the indexes of a workstream, removing the entries of its previous version that no
+    /// longer apply.
+    async fn index_workstream(&self, workstream: &Workstream) -> Result<()> {
+        let store = self.env.kv("WORKSTREAMS")?;
+        let keys = index_keys(workstream);
+        if let Some(previous) = self.get_replica(&workstream.id).await? {
+            for key in index_keys(&previous) {
+                if !keys.contains(&key) {
+                    store.delete(&key).await?;
+                }
+            }
+        }
+        for key in keys {
+            store.put(&key, workstream.id.as_str())?.execute().await?;
+        }
+        store
+            .put(&workstream_key(&workstream.id), workstream)?
+            .execute()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn unindex_workstream(&self, workstream: &Workstream) -> Result<()> {
+        let store = self.env.kv("WORKSTREAMS")?;
+        for key in index_keys(workstream) {
+            store.delete(&key).await?;
+        }
+        store
+            .delete(&workstream_key(&workstream.id))
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn get_applications(&self, workstream_id: &str) -> Result<Option<Applications>> {
+        self.env
+            .kv("APPLICATIONS")?
+            .get(workstream_id)
+            .json::<Applications>()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn put_applications(
+        &self,
+        workstream_id: &str,
+        applications: &mut Applications,
+    ) -> Result<()> {
+        applications.version += 1;
+        self.env
+            .kv("APPLICATIONS")?
+            .put(workstream_id, &*applications)?
+            .execute()
+            .await
+            .map_err(Error::from)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> Store for KvStore<'a> {
+    async fn list_users(&self) -> Result<Vec<String>> {
+        // The keys are listed in lexicographic order, so the keys of a user are adjacent.
+        let mut users: Vec<String> = self
+            .list_keys("WORKSTREAMS", "creator/")
+            .await?
+            .iter()
+            .filter_map(|key| key.split('/').nth(1).map(str::to_owned))
+            .collect();
+        users.dedup();
+        Ok(users)
+    }
+
+    async fn list_workstreams(
+        &self,
+        index: &WorkstreamIndex,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Workstream>> {
+        let store = self.env.kv("WORKSTREAMS")?;
+        let mut list = store.list().prefix(index.prefix()).limit(limit as u64);
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor.to_owned());
+        }
+        let page = list.execute().await?;
+        let mut workstreams = vec![];
+        for key in page.keys {
+            let id = key.name.rsplit('/').next().unwrap_or_default();
+            if let Some(workstream) = self.get_replica(id).await? {
+                workstreams.push(workstream);
+            }
+        }
+        Ok(Page {
+            items: workstreams,
+            next_cursor: if page.list_complete {
+                None
+            } else {
+                page.cursor
+            },
+        })
+    }
+
+    async fn list_user_workstreams(
+        &self,
+        creator: &Address,
+    ) -> Result<Option<HashMap<String, Workstream>>> {
+        let prefix = WorkstreamIndex::Creator(*creator).prefix();
+        let mut workstreams = HashMap::new();
+        for key in self.list_keys("WORKSTREAMS", &prefix).await? {
+            let id = key.trim_start_matches(&prefix);
+            if let Some(workstream) = self.get_replica(id).await? {
+                workstreams.insert(workstream.id.clone(), workstream);
+            }
+        }
+        Ok(Some(workstreams).filter(|x| !x.is_empty()))
+    }
+
+    async fn get_workstream(&self, creator: &Address, id: &str) -> Result<Option<Workstream>> {
+        Ok(self
+            .find_workstream(id)
+            .await?
+            .filter(|workstream| &workstream.creator == creator))
+    }
+
+    async fn find_workstream(&self, id: &str) -> Result<Option<Workstream>> {
+        Ok(self.command(id, Command::Get).await?.current.workstream)
+    }
+
+    async fn put_workstream(&self, workstream: &Workstream) -> Result<bool> {
+        let command = Command::Put {
+            workstream: Some(workstream.clone()),
+            applications: vec![],
+        };
+        Ok(self.command(&workstream.id, command).await?.applied)
+    }
+
+    async fn delete_workstream(
+        &self,
+        creator: &Address,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Workstream>> {
+        // The creator of a workstream never changes, so it can be checked before the command.
+        if self.get_workstream(creator, id).await?.is_none() {
+            return Ok(None);
+        }
+        let outcome = self
+            .command(id, Command::DeleteWorkstream { version })
+            .await?;
+        Ok(outcome.previous.workstream.filter(|_| outcome.applied))
+    }
+
+    async fn list_applications(
+        &self,
+        workstream_id: &str,
+    ) -> Result<Option<HashMap<String, Application>>> {
+        Ok(self
+            .get_applications(workstream_id)
+            .await?
+            .map(|blob| blob.applications))
+    }
+
+    async fn get_application(&self, workstream_id: &str, id: &str) -> Result<Option<Application>> {
+        Ok(self
+            .command(workstream_id, Command::Get)
+            .await?
+            .current
+            .applications
+            .remove(id))
+    }
+
+    async fn put_application(&self, application: &Application) -> Result<bool> {
+        let command = Command::Put {
+            workstream: None,
+            applications: vec![application.clone()],
+        };
+        Ok(self
+            .command(&application.workstream_id, command)
+            .await?
+            .applied)
+    }
+
+    async fn accept_application(
+        &self,
+        workstream: &Workstream,
+        application: &Application,
+    ) -> Result<bool> {
+        let command = Command::Put {
+            workstream: Some(workstream.clone()),
+            applications: vec![application.clone()],
+        };
+        Ok(self.command(&workstream.id, command).await?.applied)
+    }
+
+    async fn delete_application(
+        &self,
+        workstream_id: &str,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Application>> {
+        let command = Command::DeleteApplication {
+            id: id.to_owned(),
+            version,
+        };
+        let mut outcome = self.command(workstream_id, command).await?;
+        Ok(outcome
+            .previous
+            .applications
+            .remove(id)
+            .filter(|_| outcome.applied))
+    }
+
+    async fn get_authorization(&self, token: &str) -> Result<Option<Authorization>> {
+        self.env
+            .kv("AUTHENTICATION")?
+            .get(token)
+            .json::<Authorization>()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn put_authorization(
+        &self,
+        token: &str,
+        authorization: &Authorization,
+        expiration: Option<u64>,
+    ) -> Result<()> {
+        let store = self.env.kv("AUTHENTICATION")?;
+        let mut put = store.put(token, authorization)?;
+        let mut index = store.put(&session_key(&authorization.address, token), token)?;
+        if let Some(expiration) = expiration {
+            put = put.expiration(expiration);
+            index = index.expiration(expiration);
+        }
+        put.execute().await?;
+        index.execute().await.map_err(Error::from)
+    }
+
+    async fn delete_authorization(&self, token: &str) -> Result<()> {
+        let store = self.env.kv("AUTHENTICATION")?;
+        if let Some(authorization) = self.get_authorization(token).await? {
+            store
+                .delete(&session_key(&authorization.address, token))
+                .await?;
+        }
+        store.delete(token).await.map_err(Error::from)
+    }
+
+    async fn list_authorizations(&self, address: &Address) -> Result<Vec<(String, Authorization)>> {
+        let prefix = session_key(address, "");
+        let mut authorizations = vec![];
+        for key in self.list_keys("AUTHENTICATION", &prefix).await? {
+            let token = key.trim_start_matches(&prefix);
+            if let Some(authorization) = self.get_authorization(token).await? {
+                authorizations.push((token.to_owned(), authorization));
+            }
+        }
+        Ok(authorizations)
+    }
+
+    async fn put_nonce(&self, nonce: &str, ttl: u64) -> Result<()> {
+        let expires_at = Utc::now().timestamp() + ttl as i64;
+        self.env
+            .kv("AUTHENTICATION")?
+            .put(&nonce_key(expires_at, nonce), "")?
+            .execute()
+            .await?;
+        self.nonce_command(nonce, NonceCommand::Put { expires_at })
+            .await?;
+        Ok(())
+    }
+
+    async fn take_nonce(&self, nonce: &str) -> Result<bool> {
+        self.nonce_command(nonce, NonceCommand::Take).await
+    }
+
+    async fn get_currency(
+        &self,
+        chain_id: u64,
+        symbol: &PaymentCurrency,
+    ) -> Result<Option<Currency>> {
+        self.env
+            .kv("DRIPSHUBS")?
+            .get(&currency_key(chain_id, symbol))
+            .json::<Currency>()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn put_currency(&self, currency: &Currency) -> Result<()> {
+        self.env
+            .kv("DRIPSHUBS")?
+            .put(
+                &currency_key(currency.chain_id, &currency.symbol),
+                serde_json::to_string(currency)?,
+            )?
+            .execute()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn list_currencies(&self) -> Result<Vec<Currency>> {
+        let store = self.env.kv("DRIPSHUBS")?;
+        let mut currencies = vec![];
+        // The first version of the API stored the address of the DripsHub of every currency under
+        // the bare name of the currency. Those entries aren't currencies of the registry.
+        let keys = self.list_keys("DRIPSHUBS", "").await?;
+        for key in keys.iter().filter(|key| is_currency_key(key)) {
+            if let Some(currency) = store.get(key).json::<Currency>().await? {
+                currencies.push(currency);
+            }
+        }
+        currencies
+            .sort_by(|a, b| (a.chain_id, a.symbol.symbol()).cmp(&(b.chain_id, b.symbol.symbol())));
+        Ok(currencies)
+    }
+
+    async fn delete_currency(&self, chain_id: u64, symbol: &PaymentCurrency) -> Result<()> {
+        self.env
+            .kv("DRIPSHUBS")?
+            .delete(&currency_key(chain_id, symbol))
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn get_ledger(&self, workstream_id: &str) -> Result<Option<Ledger>> {
+        self.env
+            .kv("WORKSTREAMS")?
+            .get(&ledger_key(workstream_id))
+            .json::<Ledger>()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn put_ledger(&self, ledger: &Ledger) -> Result<()> {
+        self.env
+            .kv("WORKSTREAMS")?
+            .put(&ledger_key(&ledger.workstream_id), ledger)?
+            .execute()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn get_indexed_block(&self, chain_id: u64, drips_hub: &Address) -> Result<Option<u64>> {
+        self.env
+            .kv("WORKSTREAMS")?
+            .get(&indexed_block_key(chain_id, drips_hub))
+            .json::<u64>()
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn put_indexed_block(
+        &self,
+        chain_id: u64,
+        drips_hub: &Address,
+        block: u64,
+    ) -> Result<()> {
+        self.env
+            .kv("WORKSTREAMS")?
+            .put(&indexed_block_key(chain_id, drips_hub), block)?
+            .execute()
+            .await
+            .map_err(Error::from)
+    }
+}
+
+/// Copies a value through JSON, as it's stored in the KV namespaces and the Durable Objects, so
+/// that the MemoryStore loses whatever doesn't survive the JSON form of a value.
+fn json_copy<T: Serialize + DeserializeOwned>(value: &T) -> Result<T> {
+    Ok(serde_json::from_value(serde_json::to_value(value)?)?)
+}
+
+/// A Store that keeps everything in memory. It doesn't depend on the Cloudflare runtime, so it
+/// can be used to exercise the API natively (e.g in tests). Authorizations never expire.
+/// The aggregates go through the same `WorkstreamAggregate::apply` as in the WorkstreamObjects,
+/// and their replicas are updated right after every write.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// workstream id => WorkstreamAggregate
+    objects: RefCell<HashMap<String, WorkstreamAggregate>>,
+    /// The keys of the workstream indexes, in the same layout as the `WORKSTREAMS` namespace of
+    /// the KvStore, mapped to the workstream id.
+    index: RefCell<BTreeMap<String, String>>,
+    /// The copies of the workstreams in the `id` index.
+    workstreams: RefCell<HashMap<String, Workstream>>,
+    applications: RefCell<HashMap<String, HashMap<String, Application>>>,
+    authorizations: RefCell<HashMap<String, Authorization>>,
+    /// nonce => UNIX timestamp (in seconds) after which the nonce expires
+    nonces: RefCell<HashMap<String, i64>>,
+    /// (chain id, symbol) => Currency
+    currencies: RefCell<BTreeMap<(u64, String), Currency>>,
+    /// workstream id => Ledger
+    ledgers: RefCell<HashMap<String, Ledger>>,
+    /// (chain id, DripsHub) => the last indexed block
+    indexed_blocks: RefCell<HashMap<(u64, Address), u64>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Applies a command to the aggregate of a workstream and updates its replicas.
+    /// The command and its outcome are copied through JSON, like the requests and the responses
+    /// of the WorkstreamObjects.
+    fn command(&self, workstream_id: &str, command: Command) -> Result<Outcome> {
+        let outcome = self
+            .objects
+            .borrow_mut()
+            .entry(workstream_id.to_owned())
+            .or_default()
+            .apply(json_copy(&command)?);
+        if outcome.applied {
+            self.replicate(workstream_id, &outcome);
+        }
+        json_copy(&outcome)
+    }
+
+    fn replicate(&self, workstream_id: &str, outcome: &Outcome) {
+        let mut index = self.index.borrow_mut();
+        index.retain(|_, id| id != workstream_id);
+        match &outcome.current.workstream {
+            Some(workstream) => {
+                index.insert(workstream_key(workstream_id), workstream_id.to_owned());
+                for key in index_keys(workstream) {
+                    index.insert(key, workstream_id.to_owned());
+                }
+                self.workstreams
+                    .borrow_mut()
+                    .insert(workstream_id.to_owned(), workstream.clone());
+                self.applications.borrow_mut().insert(
+                    workstream_id.to_owned(),
+                    outcome.current.applications.clone(),
+                );
+            }
+            None => {
+                self.workstreams.borrow_mut().remove(workstream_id);
+                self.applications.borrow_mut().remove(workstream_id);
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for MemoryStore {
+    async fn list_users(&self) -> Result<Vec<String>> {
+        let mut users: Vec<String> = self
+            .index
+            .borrow()
+            .keys()
+            .filter(|key| key.starts_with("creator/"))
+            .filter_map(|key| key.split('/').nth(1).map(str::to_owned))
+            .collect();
+        users.dedup();
+        Ok(users)
+    }
+
+    async fn list_workstreams(
+        &self,
+        index: &WorkstreamIndex,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Workstream>> {
+        let prefix = index.prefix();
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(cursor.to_owned()),
+            None => Bound::Included(prefix.clone()),
+        };
+        let keys = self.index.borrow();
+        let mut entries = keys
+            .range((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(&prefix));
+        let page: Vec<(&String, &String)> = entries.by_ref().take(limit).collect();
+        let next_cursor = match (page.last(), entries.next()) {
+            (Some((key, _)), Some(_)) => Some(key.to_string()),
+            _ => None,
+        };
+        let workstreams = self.workstreams.borrow();
+        let items = page
+            .into_iter()
+            .filter_map(|(_, id)| workstreams.get(id).cloned())
+            .collect();
+        Ok(Page { items, next_cursor })
+    }
+
+    async fn list_user_workstreams(
+        &self,
+        creator: &Address,
+    ) -> Result<Option<HashMap<String, Workstream>>> {
+        let prefix = WorkstreamIndex::Creator(*creator).prefix();
+        let workstreams = self.workstreams.borrow();
+        let user_workstreams: HashMap<String, Workstream> = self
+            .index
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter_map(|(_, id)| workstreams.get(id).map(|x| (id.clone(), x.clone())))
+            .collect();
+        Ok(Some(user_workstreams).filter(|x| !x.is_empty()))
+    }
+
+    async fn get_workstream(&self, creator: &Address, id: &str) -> Result<Option<Workstream>> {
+        Ok(self
+            .find_workstream(id)
+            .await?
+            .filter(|workstream| &workstream.creator == creator))
+    }
+
+    async fn find_workstream(&self, id: &str) -> Result<Option<Workstream>> {
+        Ok(self
+            .objects
+            .borrow()
+            .get(id)
+            .and_then(|aggregate| aggregate.workstream.clone()))
+    }
+
+    async fn put_workstream(&self, workstream: &Workstream) -> Result<bool> {
+        let command = Command::Put {
+            workstream: Some(workstream.clone()),
+            applications: vec![],
+        };
+        Ok(self.command(&workstream.id, command)?.applied)
+    }
+
+    async fn delete_workstream(
+        &self,
+        creator: &Address,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Workstream>> {
+        if self.get_workstream(creator, id).await?.is_none() {
+            return Ok(None);
+        }
+        let outcome = self.command(id, Command::DeleteWorkstream { version })?;
+        Ok(outcome.previous.workstream.filter(|_| outcome.applied))
+    }
+
+    async fn list_applications(
+        &self,
+        workstream_id: &str,
+    ) -> Result<Option<HashMap<String, Application>>> {
+        Ok(self.applications.borrow().get(workstream_id).cloned())
+    }
+
+    async fn get_application(&self, workstream_id: &str, id: &str) -> Result<Option<Application>> {
+        Ok(self
+            .objects
+            .borrow()
+            .get(workstream_id)
+            .and_then(|aggregate| aggregate.applications.get(id).cloned()))
+    }
+
+    async fn put_application(&self, application: &Application) -> Result<bool> {
+        let command = Command::Put {
+            workstream: None,
+            applications: vec![application.clone()],
+        };
+        Ok(self.command(&application.workstream_id, command)?.applied)
+    }
+
+    async fn accept_application(
+        &self,
+        workstream: &Workstream,
+        application: &Application,
+    ) -> Result<bool> {
+        let command = Command::Put {
+            workstream: Some(workstream.clone()),
+            applications: vec![application.clone()],
+        };
+        Ok(self.command(&workstream.id, command)?.applied)
+    }
+
+    async fn delete_application(
+        &self,
+        workstream_id: &str,
+        id: &str,
+        version: u64,
+    ) -> Result<Option<Application>> {
+        let command = Command::DeleteApplication {
+            id: id.to_owned(),
+            version,
+        };
+        let mut outcome = self.command(workstream_id, command)?;
+        Ok(outcome
+            .previous
+            .applications
+            .remove(id)
+            .filter(|_| outcome.applied))
+    }
+
+    async fn get_authorization(&self, token: &str) -> Result<Option<Authorization>> {
+        Ok(self.authorizations.borrow().get(token).cloned())
+    }
+
+    async fn put_authorization(
+        &self,
+        token: &str,
+        authorization: &Authorization,
+        _expiration: Option<u64>,
+    ) -> Result<()> {
+        self.authorizations
+            .borrow_mut()
+            .insert(token.to_owned(), json_copy(authorization)?);
+        Ok(())
+    }
+
+    async fn delete_authorization(&self, token: &str) -> Result<()> {
+        self.authorizations.borrow_mut().remove(token);
+        Ok(())
+    }
+
+    async fn list_authorizations(&self, address: &Address) -> Result<Vec<(String, Authorization)>> {
+        Ok(self
+            .authorizations
+            .borrow()
+            .iter()
+            .filter(|(_, authorization)| &authorization.address == address)
+            .map(|(token, authorization)| (token.clone(), authorization.clone()))
+            .collect())
+    }
+
+    /// The nonces that expired are dropped, like the KvStore sweeps their objects.
+    async fn put_nonce(&self, nonce: &str, ttl: u64) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let mut nonces = self.nonces.borrow_mut();
+        nonces.retain(|_, expires_at| *expires_at > now);
+        nonces.insert(nonce.to_owned(), now + ttl as i64);
+        Ok(())
+    }
+
+    async fn take_nonce(&self, nonce: &str) -> Result<bool> {
+        Ok(match self.nonces.borrow_mut().remove(nonce) {
+            Some(expiration) => expiration > Utc::now().timestamp(),
+            None => false,
+        })
+    }
+
+    async fn get_currency(
+        &self,
+        chain_id: u64,
+        symbol: &PaymentCurrency,
+    ) -> Result<Option<Currency>> {
+        Ok(self
+            .currencies
+            .borrow()
+            .get(&(chain_id, symbol.to_string()))
+            .cloned())
+    }
+
+    async fn put_currency(&self, currency: &Currency) -> Result<()> {
+        self.currencies.borrow_mut().insert(
+            (currency.chain_id, currency.symbol.to_string()),
+            json_copy(currency)?,
+        );
+        Ok(())
+    }
+
+    async fn list_currencies(&self) -> Result<Vec<Currency>> {
+        Ok(self.currencies.borrow().values().cloned().collect())
+    }
+
+    async fn delete_currency(&self, chain_id: u64, symbol: &PaymentCurrency) -> Result<()> {
+        self.currencies
+            .borrow_mut()
+            .remove(&(chain_id, symbol.to_string()));
+        Ok(())
+    }
+
+    async fn get_ledger(&self, workstream_id: &str) -> Result<Option<Ledger>> {
+        Ok(self.ledgers.borrow().get(workstream_id).cloned())
+    }
+
+    async fn put_ledger(&self, ledger: &Ledger) -> Result<()> {
+        self.ledgers
+            .borrow_mut()
+            .insert(ledger.workstream_id.clone(), json_copy(ledger)?);
+        Ok(())
+    }
+
+    async fn get_indexed_block(&self, chain_id: u64, drips_hub: &Address) -> Result<Option<u64>> {
+        Ok(self
+            .indexed_blocks
+            .borrow()
+            .get(&(chain_id, *drips_hub))
+            .copied())
+    }
+
+    async fn put_indexed_block(
+        &self,
+        chain_id: u64,
+        drips_hub: &Address,
+        block: u64,
+    ) -> Result<()> {
+        self.indexed_blocks
+            .borrow_mut()
+            .insert((chain_id, *drips_hub), block);
+        Ok(())
+    }
+}
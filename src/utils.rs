@@ -10,3 +10,15 @@ cfg_if! {
         pub fn set_panic_hook() {}
     }
 }
+
+/// Logs a message to the console of the worker. When the API is not running inside the Cloudflare
+/// runtime (e.g in the tests), the message is printed to stdout instead.
+pub fn log(message: &str) {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            worker::console_log!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::currencies::Currency;
+use super::error::{ApiError, ApiResult};
+use ethers::types::U256;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// The limits that the payloads of the API must respect, so that a single request can't store
+/// arbitrarily large workstreams or applications. They are configurable (see `Config`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum length of a title, in characters (`MAX_TITLE_LENGTH`).
+    pub max_title_length: usize,
+    /// The maximum length of a description, in characters (`MAX_DESCRIPTION_LENGTH`).
+    pub max_description_length: usize,
+    /// The maximum number of receivers of a workstream or an application (`MAX_RECEIVERS`).
+    pub max_receivers: usize,
+    /// The maximum `payment_rate` of a receiver, in units of the currency per second
+    /// (`MAX_PAYMENT_RATE`), e.g `1` for 1 DAI per second. A currency can set its own (see
+    /// `Currency`).
+    pub max_payment_rate: u64,
+}
+
+impl Limits {
+    /// The maximum amount per second that a receiver can be paid in `currency`, in the smallest
+    /// unit of the currency.
+    pub fn max_amount_per_second(&self, currency: &Currency) -> U256 {
+        let units = currency.max_payment_rate.unwrap_or(self.max_payment_rate);
+        U256::from(units).saturating_mul(U256::exp10(currency.decimals.into()))
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_title_length: 200,
+            max_description_length: 20_000,
+            max_receivers: 100,
+            max_payment_rate: 1,
+        }
+    }
+}
+
+/// A field of a payload that is invalid, e.g `receivers[2].payment_rate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+/// The violations of a payload. Validation doesn't stop at the first violation, so that a client
+/// can fix all of them at once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Violations(Vec<Violation>);
+
+impl Violations {
+    pub fn new() -> Violations {
+        Violations::default()
+    }
+
+    /// Records a violation of `field` if `valid` is false.
+    pub fn check<T: Into<String>>(&mut self, valid: bool, field: &str, message: T) {
+        if !valid {
+            self.0.push(Violation {
+                field: field.to_owned(),
+                message: message.into(),
+            });
+        }
+    }
+
+    /// Fails with a validation error that lists all the violations, if there's at least one.
+    pub fn into_result(self) -> ApiResult<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Violations(self.0))
+        }
+    }
+}
+
+/// A payload of the API that can be validated field by field.
+pub trait Validate {
+    /// Records the violations of the payload in `violations`. The fields are named relative to
+    /// `path`, which is the path of the payload in the body of the request (empty for the root).
+    fn validate(&self, limits: &Limits, path: &str, violations: &mut Violations);
+}
+
+/// The path of a field under `path`.
+pub fn field(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// Records the violations of a mandatory text field, like a title or a description.
+pub fn check_text(violations: &mut Violations, field: &str, text: &str, max_length: usize) {
+    violations.check(!text.trim().is_empty(), field, "must not be empty");
+    violations.check(
+        text.chars().count() <= max_length,
+        field,
+        format!("must be at most {} characters long", max_length),
+    );
+}
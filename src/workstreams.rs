@@ -1,54 +1,119 @@
-use ethers::types::Address;
-use serde::{Deserialize, Serialize};
+use super::chain::{self, Chain, DripsState};
+use super::currencies::{self, Currency};
+use super::dates::{self, format_date};
+use super::error::{ApiError, ApiResult};
+use super::filters::WorkstreamFilter;
+use super::pagination::{SortKey, Sortable};
+use super::rates::{format_units, PaymentRate};
+use super::store::Store;
+use super::validation::{check_text, field, Limits, Validate, Violations};
+use chrono::{DateTime, Utc};
+use ethers::types::{Address, U256};
+use schemars::JsonSchema;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::{self, Debug};
 use std::str::FromStr;
 use uuid::Uuid;
-use worker::{Date, DateInit, Env, Error};
 
-#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, Eq, PartialEq)]
 pub enum WorkstreamType {
     Role,
     Grant,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
-pub enum PaymentCurrency {
-    Dai,
+impl FromStr for WorkstreamType {
+    type Err = ApiError;
+    fn from_str(input: &str) -> ApiResult<Self> {
+        match input.to_lowercase().as_ref() {
+            "role" => Ok(WorkstreamType::Role),
+            "grant" => Ok(WorkstreamType::Grant),
+            _ => Err(ApiError::invalid("wtype", "can't parse Workstream Type")),
+        }
+    }
+}
+
+/// The symbol of the currency that a workstream is paid in, e.g `DAI`. It references a
+/// `Currency` of the registry, which is looked up when the workstream is created. Symbols are
+/// case insensitive and are kept in uppercase, so the `Dai` of the first workstreams is read as
+/// `DAI`.
+#[derive(Clone, Debug, Serialize, JsonSchema, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct PaymentCurrency(String);
+
+impl PaymentCurrency {
+    pub fn symbol(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for PaymentCurrency {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for PaymentCurrency {
+    type Err = ApiError;
+    fn from_str(input: &str) -> ApiResult<Self> {
+        let symbol = input.trim();
+        if symbol.is_empty()
+            || symbol.len() > 16
+            || !symbol.chars().all(|x| x.is_ascii_alphanumeric())
+        {
+            return Err(ApiError::invalid(
+                "payment_currency",
+                "can't parse Payment Currency",
+            ));
+        }
+        Ok(PaymentCurrency(symbol.to_uppercase()))
+    }
+}
+
+impl<'de> Deserialize<'de> for PaymentCurrency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let symbol = String::deserialize(deserializer)?;
+        PaymentCurrency::from_str(&symbol).map_err(D::Error::custom)
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub struct Application {
     #[serde(default)]
     pub id: String,
     title: String,
     description: String,
     #[serde(default)]
-    workstream_id: String,
+    pub workstream_id: String,
     #[serde(default)]
-    creator: Address,
+    #[schemars(with = "String")]
+    pub creator: Address,
     receivers: Vec<Receiver>,
     payment_currency: PaymentCurrency,
+    #[serde(default = "dates::epoch", with = "dates::lenient")]
+    #[schemars(with = "DateTime<Utc>")]
+    created_at: DateTime<Utc>,
+    #[serde(default, with = "dates::lenient_option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    starting_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "dates::lenient_option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    ending_at: Option<DateTime<Utc>>,
     #[serde(default)]
-    created_at: String,
-    starting_at: Option<String>,
-    ending_at: Option<String>,
+    pub state: ApplicationState,
+    /// Incremented on every change of the application. It's the `ETag` of the application.
     #[serde(default)]
-    state: ApplicationState,
+    pub version: u64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub struct Receiver {
+    #[schemars(with = "String")]
     address: Address,
-    payment_rate: u32,
+    payment_rate: PaymentRate,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub enum ApplicationState {
     Accepted,
     Rejected,
@@ -60,41 +125,63 @@ impl Default for ApplicationState {
         ApplicationState::Pending
     }
 }
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub struct Workstream {
     #[serde(default)]
     pub id: String,
     title: String,
     wtype: WorkstreamType,
     #[serde(default)]
-    creator: Address,
-    #[serde(default)]
-    created_at: String,
-    starting_at: Option<String>,
-    ending_at: Option<String>,
+    #[schemars(with = "String")]
+    pub creator: Address,
+    #[serde(default = "dates::epoch", with = "dates::lenient")]
+    #[schemars(with = "DateTime<Utc>")]
+    created_at: DateTime<Utc>,
+    #[serde(default, with = "dates::lenient_option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    starting_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "dates::lenient_option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    ending_at: Option<DateTime<Utc>>,
     description: String,
     #[serde(flatten)]
     drips_config: DripsConfig,
     #[serde(default)]
     pub state: WorkstreamState,
+    /// Incremented on every change of the workstream. It's the `ETag` of the workstream.
+    #[serde(default)]
+    pub version: u64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// The lifecycle of a workstream. A workstream is created `Open`, it becomes `Funded` once its
+/// drips account is funded and it's `Finished` when the work is done. An `Open` or `Funded`
+/// workstream can also be `Cancelled`. `Finished` and `Cancelled` are terminal states.
+///
+/// The state can only be changed with `Workstream::transition()`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub enum WorkstreamState {
     Funded,
     Open,
     Finished,
+    Cancelled,
+}
+
+impl fmt::Display for WorkstreamState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 impl FromStr for WorkstreamState {
-    type Err = worker::Error;
-    fn from_str(input: &str) -> Result<Self, worker::Error> {
+    type Err = ApiError;
+    fn from_str(input: &str) -> ApiResult<Self> {
         let lower = input.to_lowercase();
         match lower.as_ref() {
             "funded" => Ok(WorkstreamState::Funded),
             "open" => Ok(WorkstreamState::Open),
             "finished" => Ok(WorkstreamState::Finished),
-            _ => Err(worker::Error::from("can't parse Workstream State")),
+            "cancelled" => Ok(WorkstreamState::Cancelled),
+            _ => Err(ApiError::invalid("state", "can't parse Workstream State")),
         }
     }
 }
@@ -104,36 +191,237 @@ impl Default for WorkstreamState {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// The body of a request to change the state of a workstream.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct StateTransition {
+    pub state: WorkstreamState,
+}
+
+/// The reason why a workstream can't transition to a new state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransitionError {
+    /// The lifecycle doesn't allow moving from `from` to `to`.
+    Illegal {
+        from: WorkstreamState,
+        to: WorkstreamState,
+    },
+    /// The transition is allowed, but the workstream doesn't satisfy its preconditions.
+    Precondition {
+        from: WorkstreamState,
+        to: WorkstreamState,
+        reason: String,
+    },
+    /// The preconditions of the transition can't be checked, because the chain can't be read.
+    Chain(String),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransitionError::Illegal { from, to } => {
+                write!(f, "workstream can't transition from {} to {}", from, to)
+            }
+            TransitionError::Precondition { from, to, reason } => write!(
+                f,
+                "workstream can't transition from {} to {}: {}",
+                from, to, reason
+            ),
+            TransitionError::Chain(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A transition that the workstream doesn't allow conflicts with its current state. A chain that
+/// can't be read is an upstream error.
+impl From<TransitionError> for ApiError {
+    fn from(err: TransitionError) -> Self {
+        match err {
+            TransitionError::Chain(err) => ApiError::upstream(err),
+            _ => ApiError::Conflict(err.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub struct DripsConfig {
+    /// The chain that the workstream runs on, which the DripsHub of its currency is deployed on.
+    #[serde(default = "chain::mainnet")]
+    chain_id: u64,
     drips_acct: u32,
     payment_currency: PaymentCurrency,
-    #[serde(skip_deserializing)]
+    /// The addresses that the drips account streams funds to. Receivers are added when an
+    /// application to the workstream is accepted.
+    #[serde(default)]
+    receivers: Vec<Receiver>,
+    /// The DripsHub of the currency on the chain. It's set by the API from the registry of
+    /// currencies, and the value of a request is ignored.
+    #[serde(default)]
+    #[schemars(with = "String")]
     drips_hub: Address,
 }
 
+impl DripsConfig {
+    /// Checks if the drips account streams to exactly the receivers of the configuration, with
+    /// the same payment rates.
+    fn matches(&self, drips: &DripsState) -> bool {
+        let mut expected: Vec<(Address, U256)> = self
+            .receivers
+            .iter()
+            .map(|x| (x.address, x.payment_rate.amount_per_second()))
+            .collect();
+        let mut actual = drips.receivers.clone();
+        expected.sort();
+        actual.sort();
+        expected == actual
+    }
+}
+
 impl Workstream {
+    pub fn payment_currency(&self) -> &PaymentCurrency {
+        &self.drips_config.payment_currency
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.drips_config.chain_id
+    }
+
+    pub fn drips_hub(&self) -> Address {
+        self.drips_config.drips_hub
+    }
+
+    /// The drips account of the workstream, which is `drips_acct` of its creator.
+    pub fn drips_account(&self) -> (Address, U256) {
+        (self.creator, U256::from(self.drips_config.drips_acct))
+    }
+
+    pub fn receiver_addresses(&self) -> Vec<Address> {
+        self.drips_config
+            .receivers
+            .iter()
+            .map(|x| x.address)
+            .collect()
+    }
+
+    pub fn has_receiver(&self, address: &Address) -> bool {
+        self.drips_config
+            .receivers
+            .iter()
+            .any(|x| &x.address == address)
+    }
+
+    /// Checks if `new` changes the funding details of the workstream: its chain, its currency or
+    /// its drips configuration. The rates of `new` are compared once they are resolved against
+    /// `currency`, like `Workstream::update()` stores them, so a rate that is sent in another
+    /// form isn't a change.
+    pub fn changes_funding(&self, new: &Workstream, currency: &Currency) -> bool {
+        let mut config = DripsConfig {
+            drips_hub: self.drips_config.drips_hub,
+            ..new.drips_config.clone()
+        };
+        // a rate that can't be resolved is left as it is, and the violations are reported by
+        // the update, within its limits
+        resolve_rates(&mut config.receivers, currency, &Limits::default());
+        self.drips_config != config
+    }
+
+    /// Checks if the workstream matches all the filters of a listing. A workstream without a
+    /// date doesn't match the ranges of that date.
+    pub fn matches(&self, filter: &WorkstreamFilter) -> bool {
+        let in_range = |date: Option<DateTime<Utc>>,
+                        after: Option<DateTime<Utc>>,
+                        before: Option<DateTime<Utc>>| {
+            if after.is_none() && before.is_none() {
+                return true;
+            }
+            match date {
+                Some(date) => {
+                    after.map_or(true, |after| date >= after)
+                        && before.map_or(true, |before| date <= before)
+                }
+                None => false,
+            }
+        };
+        filter.state.map_or(true, |state| self.state == state)
+            && filter
+                .wtype
+                .as_ref()
+                .map_or(true, |wtype| &self.wtype == wtype)
+            && filter
+                .creator
+                .map_or(true, |creator| self.creator == creator)
+            && filter
+                .payment_currency
+                .as_ref()
+                .map_or(true, |currency| self.payment_currency() == currency)
+            && filter
+                .chain_id
+                .map_or(true, |chain_id| self.chain_id() == chain_id)
+            && in_range(
+                self.starting_at,
+                filter.starting_after,
+                filter.starting_before,
+            )
+            && in_range(self.ending_at, filter.ending_after, filter.ending_before)
+            && filter
+                .receiver
+                .map_or(true, |receiver| self.has_receiver(&receiver))
+            && filter.text.as_ref().map_or(true, |text| {
+                self.title.to_lowercase().contains(text)
+                    || self.description.to_lowercase().contains(text)
+            })
+    }
     /// Updates a Workstream instance with the fields from another Workstream instance. We don't
     /// update all the fields, for security reasons (e.g creator, creation_type).  The
     /// old_workstream is usually the object retrieved from the KV store and the new_workstream is
     /// the object passed by the user.
     ///
-    pub fn update(
+    /// The state of the workstream is not updated, it can only be changed with
+    /// `Workstream::transition()`.
+    ///
+    /// A new drips configuration is only accepted if it matches the drips account on-chain.
+    ///
+    /// The new workstream is validated as a whole, within `limits`, before anything is updated.
+    /// Its payment rates are resolved against `currency`, the currency of the workstream.
+    ///
+    pub async fn update(
         old_workstream: &mut Workstream,
-        new_workstream: Workstream,
-    ) -> Result<(), worker::Error> {
+        mut new_workstream: Workstream,
+        currency: &Currency,
+        chain: &dyn Chain,
+        limits: &Limits,
+    ) -> ApiResult<()> {
+        let mut violations =
+            resolve_rates(&mut new_workstream.drips_config.receivers, currency, limits);
+        new_workstream.validate(limits, "", &mut violations);
+        violations.into_result()?;
         // update drips configuration
+        if old_workstream.drips_config.chain_id != new_workstream.drips_config.chain_id {
+            return Err(ApiError::invalid("chain_id", "chain can't be changed"));
+        }
+        if old_workstream.drips_config.payment_currency
+            != new_workstream.drips_config.payment_currency
+        {
+            return Err(ApiError::invalid(
+                "payment_currency",
+                "payment currency can't be changed",
+            ));
+        }
+        new_workstream.drips_config.drips_hub = old_workstream.drips_config.drips_hub;
         if old_workstream.drips_config != new_workstream.drips_config {
-            if !Workstream::check_drips_config(
-                &old_workstream.drips_config,
+            if Workstream::check_drips_config(
                 &new_workstream.drips_config,
-            )? {
-                return Err(worker::Error::from("wrong drips configuration"));
+                old_workstream.creator,
+                currency.start_block,
+                chain,
+            )
+            .await
+            .map_err(ApiError::upstream)?
+            .is_none()
+            {
+                return Err(ApiError::validation("wrong drips configuration"));
             }
             old_workstream.drips_config = new_workstream.drips_config;
         }
-        // update dates
-        check_dates(&new_workstream.starting_at, &new_workstream.ending_at)?;
         // Update time
         old_workstream.starting_at = new_workstream.starting_at;
         old_workstream.ending_at = new_workstream.ending_at;
@@ -141,16 +429,102 @@ impl Workstream {
         old_workstream.description = new_workstream.description;
         old_workstream.wtype = new_workstream.wtype;
         old_workstream.title = new_workstream.title;
-        // update state
-        old_workstream.state = new_workstream.state;
+        old_workstream.version += 1;
+        Ok(())
+    }
+    /// Moves a workstream to a new state, following its lifecycle:
+    ///
+    /// ```text
+    /// Open ──> Funded ──> Finished
+    ///   │        │
+    ///   └────────┴──> Cancelled
+    /// ```
+    ///
+    /// Every transition can have preconditions. A workstream can become `Funded` only if its
+    /// drips configuration matches the drips account on-chain and the account has funds left.
+    /// The DripsHub of the workstream is read from `start_block` (see `Currency`).
+    pub async fn transition(
+        workstream: &mut Workstream,
+        state: WorkstreamState,
+        start_block: u64,
+        chain: &dyn Chain,
+    ) -> Result<(), TransitionError> {
+        let from = workstream.state;
+        let precondition = |reason: String| TransitionError::Precondition {
+            from,
+            to: state,
+            reason,
+        };
+        match (from, state) {
+            (WorkstreamState::Open, WorkstreamState::Funded) => {
+                let drips = Workstream::check_drips_config(
+                    &workstream.drips_config,
+                    workstream.creator,
+                    start_block,
+                    chain,
+                )
+                .await
+                .map_err(|err| TransitionError::Chain(err.to_string()))?;
+                match drips {
+                    None => {
+                        return Err(precondition(
+                            "drips configuration doesn't match the drips account".to_owned(),
+                        ))
+                    }
+                    Some(drips) => {
+                        let now = Utc::now().timestamp().unsigned_abs();
+                        if drips.balance_at(now).is_zero() {
+                            return Err(precondition("drips account is not funded".to_owned()));
+                        }
+                    }
+                }
+            }
+            (WorkstreamState::Funded, WorkstreamState::Finished)
+            | (WorkstreamState::Open, WorkstreamState::Cancelled)
+            | (WorkstreamState::Funded, WorkstreamState::Cancelled) => {}
+            _ => return Err(TransitionError::Illegal { from, to: state }),
+        }
+        workstream.state = state;
+        workstream.version += 1;
         Ok(())
     }
-    // check if passed receiver configuration actually exists on-chain
-    fn check_drips_config(
-        _old_config: &DripsConfig,
-        _new_config: &DripsConfig,
-    ) -> Result<bool, worker::Error> {
-        Ok(true)
+    /// Moves a workstream along its lifecycle, following the funding of its drips account at
+    /// `now` (UNIX timestamp in seconds), as it's recorded by the indexer: an `Open` workstream
+    /// becomes `Funded` once the account matches its drips configuration and has funds, and a
+    /// `Funded` workstream becomes `Finished` once the account runs out of funds. It returns
+    /// `true` if the state changed.
+    pub fn follow_funding(workstream: &mut Workstream, drips: &DripsState, now: u64) -> bool {
+        let funded = !drips.balance_at(now).is_zero();
+        let state = match workstream.state {
+            WorkstreamState::Open if funded && workstream.drips_config.matches(drips) => {
+                WorkstreamState::Funded
+            }
+            WorkstreamState::Funded if !funded => WorkstreamState::Finished,
+            _ => return false,
+        };
+        workstream.state = state;
+        workstream.version += 1;
+        true
+    }
+    /// Checks if the passed receiver configuration actually exists on-chain: the drips account
+    /// `drips_acct` of `creator` must stream to exactly the `receivers` of the configuration, with
+    /// the same payment rates. Returns the on-chain state of the account, if it matches.
+    async fn check_drips_config(
+        config: &DripsConfig,
+        creator: Address,
+        start_block: u64,
+        chain: &dyn Chain,
+    ) -> Result<Option<DripsState>, worker::Error> {
+        let drips = chain
+            .drips_state(
+                config.chain_id,
+                config.drips_hub,
+                start_block,
+                creator,
+                U256::from(config.drips_acct),
+            )
+            .await?;
+        Ok(drips.filter(|drips| config.matches(drips)))
     }
     /// Populate a new workstream instance passed by the user. Populate is different from update,
     /// because here the user creates an incomplete Workstream object (with some fields missing)
@@ -158,31 +532,37 @@ impl Workstream {
     /// etc.).
     ///
     /// The API is configured to use the official DripsHub contracts, which are usually tied to a
-    /// particular ERC20. The KV store for the address of the dripshubs is populated by the DevOps
-    /// team ahead of deployment. If the API didn't populate this field, the user could pass
-    /// an arbitrary smart contract, with important security implications.
+    /// particular ERC20. The registry of the currencies (see `Currency`), with the addresses of
+    /// their DripsHubs, is populated by the DevOps team ahead of deployment. If the API didn't
+    /// populate this field, the user could pass an arbitrary smart contract, with important
+    /// security implications.
+    ///
+    /// The payment rates of the workstream are resolved against its currency, and the workstream
+    /// is validated within `limits`, reporting all of its invalid fields.
     ///
     pub async fn populate(
         workstream: &mut Workstream,
         user: &str,
-        env: &Env,
-    ) -> Result<String, worker::Error> {
+        store: &dyn Store,
+        limits: &Limits,
+    ) -> ApiResult<String> {
+        let currency = currencies::lookup(
+            store,
+            workstream.drips_config.chain_id,
+            &workstream.drips_config.payment_currency,
+        )
+        .await?;
+        let mut violations =
+            resolve_rates(&mut workstream.drips_config.receivers, &currency, limits);
+        workstream.validate(limits, "", &mut violations);
+        violations.into_result()?;
         workstream.id = Uuid::new_v4().to_string();
-        workstream.creator = Address::from_str(user).map_err(|err| Error::from(err.to_string()))?;
+        workstream.creator =
+            Address::from_str(user).map_err(|err| ApiError::invalid("user", err.to_string()))?;
         workstream.state = WorkstreamState::Open;
-        check_dates(&workstream.starting_at, &workstream.ending_at)?;
-        workstream.created_at = Date::now().to_string();
-        let drips_hub: Option<String> = env
-            .kv("DRIPSHUBS")?
-            .get(&workstream.drips_config.payment_currency.to_string())
-            .text()
-            .await?;
-        if drips_hub == None {
-            return Err(Error::from("no drips hub for given currency"));
-        } else {
-            workstream.drips_config.drips_hub = Address::from_str(&drips_hub.unwrap())
-                .map_err(|err| Error::from(err.to_string()))?;
-        }
+        workstream.version = 1;
+        workstream.created_at = Utc::now();
+        workstream.drips_config.drips_hub = currency.drips_hub;
         Ok(workstream.id.to_string())
     }
 }
@@ -190,19 +570,24 @@ impl Workstream {
 impl Application {
     /// Populate a new application instance. It follows the same philosphy as
     /// Workstream::populate().
+    ///
+    /// An application is paid in the currency of its workstream, `currency`, which its payment
+    /// rates are resolved against.
     pub fn populate(
         application: &mut Application,
         user: &str,
         workstream: &str,
-    ) -> Result<(), worker::Error> {
-        check_dates(&application.starting_at, &application.ending_at)?;
-        application.created_at = Date::now().to_string();
+        currency: &Currency,
+        limits: &Limits,
+    ) -> ApiResult<()> {
+        application.resolve(currency, limits)?;
         application.id = Uuid::new_v4().to_string();
         application.workstream_id = workstream.to_string();
         application.creator =
-            Address::from_str(user).map_err(|err| Error::from(err.to_string()))?;
+            Address::from_str(user).map_err(|err| ApiError::Internal(err.to_string()))?;
         application.state = ApplicationState::Pending;
-        application.created_at = Date::now().to_string();
+        application.version = 1;
+        application.created_at = Utc::now();
         Ok(())
     }
 
@@ -210,31 +595,251 @@ impl Application {
     pub fn update(
         old_application: &Application,
         new_application: &mut Application,
-    ) -> Result<(), worker::Error> {
-        check_dates(&new_application.starting_at, &new_application.ending_at)?;
+        currency: &Currency,
+        limits: &Limits,
+    ) -> ApiResult<()> {
+        new_application.resolve(currency, limits)?;
+        new_application.id = old_application.id.clone();
         new_application.workstream_id = old_application.workstream_id.clone();
         new_application.creator = old_application.creator;
-        new_application.created_at = old_application.created_at.clone();
+        new_application.created_at = old_application.created_at;
+        new_application.state = old_application.state;
+        new_application.version = old_application.version + 1;
         Ok(())
     }
+
+    /// Resolves the payment rates of the application against `currency` and validates it within
+    /// `limits`.
+    fn resolve(&mut self, currency: &Currency, limits: &Limits) -> ApiResult<()> {
+        let mut violations = resolve_rates(&mut self.receivers, currency, limits);
+        violations.check(
+            self.payment_currency == currency.symbol,
+            "payment_currency",
+            format!(
+                "must be the currency of the workstream, {}",
+                currency.symbol
+            ),
+        );
+        self.validate(limits, "", &mut violations);
+        violations.into_result()
+    }
+
+    /// Accepts an application to a workstream. The receivers of the application are added to the
+    /// drips configuration of the workstream, replacing any existing receiver with the same
+    /// address. The workstream can't end up with more receivers than `limits` allow.
+    pub fn accept(
+        application: &mut Application,
+        workstream: &mut Workstream,
+        limits: &Limits,
+    ) -> ApiResult<()> {
+        let mut receivers = workstream.drips_config.receivers.clone();
+        for receiver in &application.receivers {
+            receivers.retain(|x| x.address != receiver.address);
+            receivers.push(receiver.clone());
+        }
+        if receivers.len() > limits.max_receivers {
+            return Err(ApiError::invalid(
+                "receivers",
+                format!(
+                    "the workstream can have at most {} receivers",
+                    limits.max_receivers
+                ),
+            ));
+        }
+        workstream.drips_config.receivers = receivers;
+        workstream.version += 1;
+        application.state = ApplicationState::Accepted;
+        application.version += 1;
+        Ok(())
+    }
+
+    pub fn reject(application: &mut Application) {
+        application.state = ApplicationState::Rejected;
+        application.version += 1;
+    }
 }
+impl Sortable for Workstream {
+    const SORT_KEYS: &'static [SortKey] =
+        &[SortKey::CreatedAt, SortKey::StartingAt, SortKey::Title];
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn sort_value(&self, key: SortKey) -> Option<String> {
+        match key {
+            SortKey::CreatedAt => Some(format_date(&self.created_at)),
+            SortKey::StartingAt => self.starting_at.as_ref().map(format_date),
+            SortKey::Title => Some(self.title.to_lowercase()),
+        }
+    }
+}
+
+impl Sortable for Application {
+    const SORT_KEYS: &'static [SortKey] =
+        &[SortKey::CreatedAt, SortKey::StartingAt, SortKey::Title];
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn sort_value(&self, key: SortKey) -> Option<String> {
+        match key {
+            SortKey::CreatedAt => Some(format_date(&self.created_at)),
+            SortKey::StartingAt => self.starting_at.as_ref().map(format_date),
+            SortKey::Title => Some(self.title.to_lowercase()),
+        }
+    }
+}
+
 /// Performs sanity check to the dates passed to either Workstream or Application
-/// with the following simple rule: `starting_a`t should be before now() and before `ending_at`
+/// with the following simple rule: `starting_at` should be after now() and before `ending_at`,
+/// which should also be after now().
 fn check_dates(
-    starting_at: &Option<String>,
-    ending_at: &Option<String>,
-) -> Result<(), worker::Error> {
+    starting_at: Option<DateTime<Utc>>,
+    ending_at: Option<DateTime<Utc>>,
+    path: &str,
+    violations: &mut Violations,
+) {
+    let now = Utc::now();
     if let Some(start) = starting_at {
-        let starting_date: Date = Date::from(DateInit::String(start.to_string()));
-        if Date::now().as_millis() > starting_date.as_millis() {
-            return Err(Error::from("incorrect starting date"));
-        }
+        violations.check(
+            start >= now,
+            &field(path, "starting_at"),
+            "must not be in the past",
+        );
     }
     if let Some(end) = ending_at {
-        let ending_date: Date = Date::from(DateInit::String(end.to_string()));
-        if ending_date.as_millis() < Date::now().as_millis() {
-            return Err(Error::from("incorrect ending date"));
+        violations.check(
+            end >= now,
+            &field(path, "ending_at"),
+            "must not be in the past",
+        );
+    }
+    if let (Some(start), Some(end)) = (starting_at, ending_at) {
+        violations.check(
+            start < end,
+            &field(path, "ending_at"),
+            "must be after starting_at",
+        );
+    }
+}
+
+/// Records the violations of the receivers of a workstream or an application. A receiver can
+/// appear only once, as the receivers of a drips account are keyed by their address.
+fn check_receivers(
+    receivers: &[Receiver],
+    limits: &Limits,
+    path: &str,
+    violations: &mut Violations,
+) {
+    let path = field(path, "receivers");
+    violations.check(
+        receivers.len() <= limits.max_receivers,
+        &path,
+        format!("must have at most {} receivers", limits.max_receivers),
+    );
+    for (i, receiver) in receivers.iter().enumerate() {
+        let receiver_path = format!("{}[{}]", path, i);
+        receiver.validate(limits, &receiver_path, violations);
+        violations.check(
+            !receivers[..i].iter().any(|x| x.address == receiver.address),
+            &field(&receiver_path, "address"),
+            "duplicate receiver",
+        );
+    }
+}
+
+/// Resolves the payment rates of the receivers of a workstream or an application against
+/// `currency` (see `PaymentRate::resolve()`). It returns the violations of the rates that can't
+/// be resolved or exceed the maximum rate of the currency within `limits`, which the rest of the
+/// violations of the payload are added to.
+fn resolve_rates(receivers: &mut [Receiver], currency: &Currency, limits: &Limits) -> Violations {
+    let mut violations = Violations::new();
+    let max = limits.max_amount_per_second(currency);
+    for (i, receiver) in receivers.iter_mut().enumerate() {
+        let path = format!("receivers[{}].payment_rate", i);
+        match receiver.payment_rate.resolve(currency) {
+            Ok(rate) => {
+                violations.check(
+                    rate.amount_per_second <= max,
+                    &path,
+                    format!(
+                        "must stream at most {} {} per second",
+                        format_units(max, currency.decimals),
+                        currency.symbol
+                    ),
+                );
+                receiver.payment_rate = PaymentRate::Resolved(rate);
+            }
+            Err(message) => violations.check(false, &path, message),
+        }
+    }
+    violations
+}
+
+impl Validate for Receiver {
+    fn validate(&self, limits: &Limits, path: &str, violations: &mut Violations) {
+        violations.check(
+            !self.address.is_zero(),
+            &field(path, "address"),
+            "must not be the zero address",
+        );
+        // a rate that can't be resolved is already a violation
+        if let PaymentRate::Human(_) = self.payment_rate {
+            return;
         }
+        // the maximum rate depends on the currency, so it's checked when the rate is resolved
+        violations.check(
+            !self.payment_rate.amount_per_second().is_zero(),
+            &field(path, "payment_rate"),
+            "must stream at least 1 of the smallest unit of the currency per second",
+        );
+    }
+}
+
+/// The fields of the drips configuration are flattened into the workstream, so they share its
+/// path.
+impl Validate for DripsConfig {
+    fn validate(&self, limits: &Limits, path: &str, violations: &mut Violations) {
+        check_receivers(&self.receivers, limits, path, violations);
+    }
+}
+
+impl Validate for Workstream {
+    fn validate(&self, limits: &Limits, path: &str, violations: &mut Violations) {
+        check_text(
+            violations,
+            &field(path, "title"),
+            &self.title,
+            limits.max_title_length,
+        );
+        check_text(
+            violations,
+            &field(path, "description"),
+            &self.description,
+            limits.max_description_length,
+        );
+        check_dates(self.starting_at, self.ending_at, path, violations);
+        self.drips_config.validate(limits, path, violations);
+    }
+}
+
+impl Validate for Application {
+    fn validate(&self, limits: &Limits, path: &str, violations: &mut Violations) {
+        check_text(
+            violations,
+            &field(path, "title"),
+            &self.title,
+            limits.max_title_length,
+        );
+        check_text(
+            violations,
+            &field(path, "description"),
+            &self.description,
+            limits.max_description_length,
+        );
+        check_dates(self.starting_at, self.ending_at, path, violations);
+        check_receivers(&self.receivers, limits, path, violations);
     }
-    Ok(())
 }
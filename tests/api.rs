@@ -0,0 +1,2281 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, SecondsFormat, TimeZone, Utc};
+use ethers::providers::{Http, Provider};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, Log, RecoveryMessage, Signature, H256, U256};
+use ethers::utils::{hash_message, to_checksum};
+use futures::executor::block_on;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use worker::Method;
+use workstreams_api::auth::Authorization;
+use workstreams_api::chain::{self, Chain, DripsEvent, DripsEventKind, DripsState, RpcChain};
+use workstreams_api::config::Config;
+use workstreams_api::cookies;
+use workstreams_api::currencies::Currency;
+use workstreams_api::dates;
+use workstreams_api::http::{ApiRequest, ApiResponse};
+use workstreams_api::indexer::{self, CONFIRMATIONS, MAX_BLOCKS};
+use workstreams_api::objects::{Command, WorkstreamAggregate};
+use workstreams_api::openapi;
+use workstreams_api::routes::{handle, Api, ROUTES};
+use workstreams_api::store::{self, MemoryStore, Store};
+use workstreams_api::workstreams::{Application, Workstream, WorkstreamState};
+
+const HOST: &str = "http://localhost:8787";
+
+/// A Chain whose drips accounts and smart contract wallets are set by the tests.
+#[derive(Default)]
+struct MockChain {
+    /// (chain id, DripsHub, user, account) => DripsState
+    drips: RefCell<HashMap<(u64, Address, Address, U256), DripsState>>,
+    /// EIP1271 wallets, like the mock contract of a devnet: a signature is valid if it was
+    /// signed by the owner of the wallet.
+    wallets: RefCell<HashMap<Address, Address>>,
+    /// The logs of the DripsHubs on mainnet, with the timestamps of their blocks.
+    logs: RefCell<Vec<(Log, u64)>>,
+    /// The latest block of every chain.
+    head: Cell<u64>,
+    /// Whether the nodes are down, so that the drips accounts can't be read.
+    down: Cell<bool>,
+}
+
+impl MockChain {
+    fn set_drips(&self, user: Address, account: u32, drips: DripsState) {
+        self.drips.borrow_mut().insert(
+            (1, Address::repeat_byte(0xd1), user, U256::from(account)),
+            drips,
+        );
+    }
+
+    fn set_wallet(&self, wallet: Address, owner: Address) {
+        self.wallets.borrow_mut().insert(wallet, owner);
+    }
+
+    /// Sets the logs of a fixture of `tests/fixtures`: DripsHub logs in the format of
+    /// `eth_getLogs`, and the timestamps of their blocks.
+    fn set_logs(&self, fixture: &str) {
+        let fixture: Value = serde_json::from_str(fixture).unwrap();
+        let timestamps: HashMap<String, u64> =
+            serde_json::from_value(fixture["blocks"].clone()).unwrap();
+        let logs: Vec<Log> = serde_json::from_value(fixture["logs"].clone()).unwrap();
+        *self.logs.borrow_mut() = logs
+            .into_iter()
+            .map(|log| {
+                let block_number = log.block_number.unwrap().to_string();
+                (log, timestamps[&block_number])
+            })
+            .collect();
+    }
+}
+
+#[async_trait(?Send)]
+impl Chain for MockChain {
+    async fn drips_state(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        _start_block: u64,
+        user: Address,
+        account: U256,
+    ) -> worker::Result<Option<DripsState>> {
+        if self.down.get() {
+            return Err(worker::Error::from("rpc: node is down"));
+        }
+        Ok(self
+            .drips
+            .borrow()
+            .get(&(chain_id, drips_hub, user, account))
+            .cloned())
+    }
+
+    async fn is_valid_signature(
+        &self,
+        _chain_id: u64,
+        wallet: Address,
+        hash: H256,
+        signature: &[u8],
+    ) -> worker::Result<bool> {
+        let owner = match self.wallets.borrow().get(&wallet) {
+            Some(owner) => *owner,
+            None => return Ok(false),
+        };
+        Ok(Signature::try_from(signature)
+            .and_then(|signature| signature.recover(RecoveryMessage::Hash(hash)))
+            .map(|signer| signer == owner)
+            .unwrap_or(false))
+    }
+
+    async fn block_number(&self, _chain_id: u64) -> worker::Result<u64> {
+        Ok(self.head.get())
+    }
+
+    async fn drips_events(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        from: u64,
+        to: u64,
+    ) -> worker::Result<Vec<DripsEvent>> {
+        let abi = chain::drips_hub_abi()?;
+        let mut events = vec![];
+        for (log, timestamp) in self.logs.borrow().iter() {
+            let block_number = log.block_number.unwrap().as_u64();
+            if chain_id == 1
+                && log.address == drips_hub
+                && from <= block_number
+                && block_number <= to
+            {
+                events.extend(chain::decode_drips_log(&abi, log.clone(), *timestamp)?);
+            }
+        }
+        Ok(events)
+    }
+
+    async fn collected(
+        &self,
+        chain_id: u64,
+        drips_hub: Address,
+        start_block: u64,
+        user: Address,
+    ) -> worker::Result<U256> {
+        let events = self
+            .drips_events(chain_id, drips_hub, start_block, self.head.get())
+            .await?;
+        Ok(events
+            .iter()
+            .fold(U256::zero(), |total, event| match event.kind {
+                DripsEventKind::Collected {
+                    user: collector,
+                    collected,
+                    ..
+                } if collector == user => total + collected,
+                _ => total,
+            }))
+    }
+}
+
+fn store() -> MemoryStore {
+    let store = MemoryStore::new();
+    block_on(store.put_currency(&currency("DAI", 0xd1))).unwrap();
+    store
+}
+
+/// A currency of the registry, streamed by the DripsHub at `Address::repeat_byte(drips_hub)`.
+fn currency(symbol: &str, drips_hub: u8) -> Currency {
+    Currency {
+        symbol: symbol.parse().unwrap(),
+        address: Address::repeat_byte(!drips_hub),
+        decimals: 18,
+        chain_id: 1,
+        drips_hub: Address::repeat_byte(drips_hub),
+        start_block: 0,
+        max_payment_rate: None,
+    }
+}
+
+fn config() -> Config {
+    Config {
+        domain: "localhost:8787".to_owned(),
+        uri: HOST.to_owned(),
+        chain_ids: vec![1],
+        ..Config::default()
+    }
+}
+
+fn request(method: Method, path: &str) -> ApiRequest {
+    ApiRequest::new(method, &format!("{}{}", HOST, path)).unwrap()
+}
+
+fn send(api: &Api, req: ApiRequest) -> ApiResponse {
+    block_on(handle(api, req)).unwrap()
+}
+
+/// The value of the `Authorization` header that sends `token`.
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+/// The `ETag` of a workstream or an application, as returned by the API.
+fn etag(resource: &Value) -> String {
+    format!("\"{}\"", resource["version"])
+}
+
+fn address(wallet: &LocalWallet) -> String {
+    format!("{:?}", wallet.address())
+}
+
+/// Builds an EIP-4361 message for `wallet` and `domain`, valid for the next hour.
+fn siwe_message(wallet: &LocalWallet, domain: &str, nonce: &str) -> String {
+    let now = Utc::now();
+    format!(
+        "{} wants you to sign in with your Ethereum account:\n\
+         {}\n\n\
+         Sign in to Workstreams\n\n\
+         URI: {}\n\
+         Version: 1\n\
+         Chain ID: 1\n\
+         Nonce: {}\n\
+         Issued At: {}\n\
+         Expiration Time: {}",
+        domain,
+        to_checksum(&wallet.address(), None),
+        HOST,
+        nonce,
+        now.to_rfc3339_opts(SecondsFormat::Millis, true),
+        (now + Duration::hours(1)).to_rfc3339_opts(SecondsFormat::Millis, true),
+    )
+}
+
+fn nonce(api: &Api) -> String {
+    let res = send(api, request(Method::Get, "/api/v1/nonce"));
+    assert_eq!(res.status, 200);
+    res.body
+}
+
+/// Signs `message` with `wallet` and sends it to `/api/v1/authorize`.
+fn authorize(api: &Api, wallet: &LocalWallet, message: &str) -> ApiResponse {
+    let signature = block_on(wallet.sign_message(message)).unwrap();
+    send(
+        api,
+        request(Method::Post, "/api/v1/authorize")
+            .with_json(&json!({ "message": message, "signature": signature.to_string() }))
+            .unwrap(),
+    )
+}
+
+/// Signs in with `wallet` and returns the authorization token.
+fn login(api: &Api, wallet: &LocalWallet) -> String {
+    let message = siwe_message(wallet, "localhost:8787", &nonce(api));
+    token(&authorize(api, wallet, &message))
+}
+
+/// Returns the authorization token of a successful `/api/v1/authorize` response.
+fn token(res: &ApiResponse) -> String {
+    assert_eq!(res.status, 200, "{}", res.body);
+    let cookie = res.header("Set-Cookie").expect("no cookie was set");
+    cookie
+        .split(';')
+        .next()
+        .and_then(|x| x.strip_prefix("SIWE-AUTH="))
+        .expect("no SIWE-AUTH cookie")
+        .to_owned()
+}
+
+/// Creates a workstream owned by `user` and returns it.
+fn create_workstream(api: &Api, user: &str, token: &str) -> Value {
+    let res = send(
+        api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(token))
+            .with_json(&workstream())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    res.json().unwrap()
+}
+
+fn workstream() -> Value {
+    json!({
+        "title": "Radicle Drips integration",
+        "wtype": "Grant",
+        "description": "lorem ipsum",
+        "drips_acct": 0,
+        "payment_currency": "Dai"
+    })
+}
+
+fn application() -> Value {
+    json!({
+        "title": "I want to work on this",
+        "description": "lorem ipsum",
+        "payment_currency": "Dai",
+        "receivers": [
+            {
+                "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b",
+                "payment_rate": 150
+            }
+        ]
+    })
+}
+
+#[test]
+fn authorize_create_workstream_and_apply() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+
+    let res = send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&workstream())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let created: Value = res.json().unwrap();
+    let workstream_id = created["id"].as_str().unwrap().to_owned();
+    assert_eq!(created["creator"], json!(user));
+    assert_eq!(created["state"], json!("Open"));
+    assert_eq!(
+        created["drips_hub"],
+        json!(format!("{:?}", Address::repeat_byte(0xd1)))
+    );
+
+    let res = send(&api, request(Method::Get, "/api/v1/workstreams?state=open"));
+    let listed: Value = res.json().unwrap();
+    assert_eq!(listed["items"].as_array().unwrap().len(), 1);
+    assert_eq!(listed["items"][0]["id"], json!(workstream_id));
+
+    let res = send(
+        &api,
+        request(
+            Method::Get,
+            &format!("/api/v1/users/{}/workstreams/{}", user, workstream_id),
+        ),
+    );
+    assert_eq!(res.status, 200);
+    assert_eq!(res.json::<Value>().unwrap(), created);
+
+    let applicant = LocalWallet::new(&mut rand::thread_rng());
+    let applicant_token = login(&api, &applicant);
+    let applications = format!(
+        "/api/v1/users/{}/workstreams/{}/applications",
+        user, workstream_id
+    );
+    let res = send(
+        &api,
+        request(Method::Post, &applications)
+            .with_header("Authorization", &bearer(&applicant_token))
+            .with_json(&application())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let applied: Value = res.json().unwrap();
+    assert_eq!(applied["workstream_id"], json!(workstream_id));
+    assert_eq!(applied["creator"], json!(address(&applicant)));
+    assert_eq!(applied["state"], json!("Pending"));
+
+    let res = send(&api, request(Method::Get, &applications));
+    let listed: Value = res.json().unwrap();
+    assert_eq!(listed["items"], json!([applied]));
+}
+
+#[test]
+fn unauthorized_requests_are_rejected() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let intruder = LocalWallet::new(&mut rand::thread_rng());
+    let path = format!("/api/v1/users/{}/workstreams", address(&owner));
+
+    let res = send(
+        &api,
+        request(Method::Post, &path)
+            .with_json(&workstream())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 401);
+
+    let token = login(&api, &intruder);
+    let res = send(
+        &api,
+        request(Method::Post, &path)
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&workstream())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 401);
+
+    let res = send(&api, request(Method::Get, &path));
+    assert_eq!(res.status, 404);
+}
+
+#[test]
+fn unknown_routes_are_not_found() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let res = send(&api, request(Method::Get, "/api/v1/unknown"));
+    assert_eq!(res.status, 404);
+}
+
+/// Collects the `$ref`s of a JSON document.
+fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => found.push(reference),
+                    _ => refs(value, found),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+        _ => {}
+    }
+}
+
+#[test]
+fn every_route_is_in_the_openapi_document() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let res = send(&api, request(Method::Get, "/api/v1/openapi.json"));
+    assert_eq!(res.status, 200);
+    let document = res.json::<Value>().unwrap();
+
+    for (pattern, _) in ROUTES {
+        let item = &document["paths"][openapi::path(pattern)];
+        assert!(item.is_object(), "{} is missing from the document", pattern);
+        assert!(
+            ["get", "post", "put", "delete"]
+                .iter()
+                .any(|method| item[*method].is_object()),
+            "{} has no operations",
+            pattern
+        );
+    }
+    let schemas = &document["components"]["schemas"];
+    for field in &["title", "wtype", "created_at", "drips_acct", "receivers"] {
+        assert!(schemas["Workstream"]["properties"][*field].is_object());
+    }
+    assert!(schemas["Application"]["properties"]["title"].is_object());
+    assert!(schemas["AuthRequest"]["properties"]["signature"].is_object());
+
+    let mut references = vec![];
+    refs(&document, &mut references);
+    assert!(!references.is_empty());
+    for reference in references {
+        let pointer = reference.trim_start_matches('#');
+        assert!(
+            document.pointer(pointer).is_some(),
+            "{} is dangling",
+            reference
+        );
+    }
+}
+
+#[test]
+fn workstreams_are_paid_in_a_currency_of_the_registry() {
+    let store = store();
+    block_on(store.put_currency(&currency("usdc", 0xd2))).unwrap();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+
+    let res = send(&api, request(Method::Get, "/api/v1/currencies"));
+    assert_eq!(res.status, 200);
+    let currencies = res.json::<Value>().unwrap();
+    let symbols: Vec<&Value> = currencies
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| &x["symbol"])
+        .collect();
+    assert_eq!(symbols, vec![&json!("DAI"), &json!("USDC")]);
+    assert_eq!(currencies[1]["decimals"], json!(18));
+
+    let create = |payment_currency: &str| {
+        let mut workstream = workstream();
+        workstream["payment_currency"] = json!(payment_currency);
+        send(
+            &api,
+            request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&workstream)
+                .unwrap(),
+        )
+    };
+    let res = create("Usdc");
+    assert_eq!(res.status, 200, "{}", res.body);
+    let created = res.json::<Value>().unwrap();
+    assert_eq!(created["payment_currency"], json!("USDC"));
+    assert_eq!(
+        created["drips_hub"],
+        json!(format!("{:?}", Address::repeat_byte(0xd2)))
+    );
+    let res = send(
+        &api,
+        request(Method::Get, "/api/v1/workstreams?payment_currency=usdc"),
+    );
+    assert_eq!(
+        res.json::<Value>().unwrap()["items"][0]["id"],
+        created["id"]
+    );
+
+    let res = create("EUR");
+    assert_eq!(res.status, 400);
+    assert_eq!(
+        res.json::<Value>().unwrap()["field"],
+        json!("payment_currency")
+    );
+    assert_eq!(create("not a symbol").status, 400);
+}
+
+#[test]
+fn workstreams_run_on_the_chain_of_their_session() {
+    let store = store();
+    block_on(store.put_currency(&Currency {
+        chain_id: 10,
+        ..currency("DAI", 0xd2)
+    }))
+    .unwrap();
+    let chain = MockChain::default();
+    let api = Api::new(
+        &store,
+        &chain,
+        Config {
+            chain_ids: vec![1, 10],
+            ..config()
+        },
+    );
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let mainnet = login(&api, &wallet);
+    let login_on = |chain_id: u64| {
+        let message = siwe_message(&wallet, "localhost:8787", &nonce(&api))
+            .replace("Chain ID: 1", &format!("Chain ID: {}", chain_id));
+        authorize(&api, &wallet, &message)
+    };
+    assert_eq!(login_on(5).status, 401);
+    let optimism = token(&login_on(10));
+
+    let create = |token: &str, chain_id: u64, payment_currency: &str| {
+        let mut workstream = workstream();
+        workstream["chain_id"] = json!(chain_id);
+        workstream["payment_currency"] = json!(payment_currency);
+        send(
+            &api,
+            request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+                .with_header("Authorization", &bearer(token))
+                .with_json(&workstream)
+                .unwrap(),
+        )
+    };
+    let res = create(&mainnet, 10, "DAI");
+    assert_eq!(res.status, 403, "{}", res.body);
+    assert_eq!(create(&optimism, 10, "USDC").status, 400);
+    let res = create(&optimism, 10, "DAI");
+    assert_eq!(res.status, 200, "{}", res.body);
+    let created = res.json::<Value>().unwrap();
+    assert_eq!(created["chain_id"], json!(10));
+    assert_eq!(
+        created["drips_hub"],
+        json!(format!("{:?}", Address::repeat_byte(0xd2)))
+    );
+    assert_eq!(create(&mainnet, 1, "DAI").status, 200);
+
+    let list = |chain_id: u64| {
+        let res = send(
+            &api,
+            request(
+                Method::Get,
+                &format!("/api/v1/workstreams?chain_id={}", chain_id),
+            ),
+        );
+        res.json::<Value>().unwrap()["items"].clone()
+    };
+    assert_eq!(list(10).as_array().unwrap().len(), 1);
+    assert_eq!(list(10)[0]["id"], created["id"]);
+    assert_eq!(list(1).as_array().unwrap().len(), 1);
+
+    let edit = |token: &str, workstream: &Value| {
+        send(
+            &api,
+            request(
+                Method::Put,
+                &format!(
+                    "/api/v1/users/{}/workstreams/{}",
+                    user,
+                    created["id"].as_str().unwrap()
+                ),
+            )
+            .with_header("Authorization", &bearer(token))
+            .with_header("If-Match", &etag(workstream))
+            .with_json(workstream)
+            .unwrap(),
+        )
+    };
+    // only the metadata changes, so the session can be on any chain
+    let mut renamed = created.clone();
+    renamed["title"] = json!("Renamed");
+    assert_eq!(edit(&mainnet, &renamed).status, 200);
+    renamed["version"] = json!(2);
+    let mut moved = renamed.clone();
+    moved["chain_id"] = json!(1);
+    assert_eq!(edit(&mainnet, &moved).status, 403);
+    let res = edit(&optimism, &moved);
+    assert_eq!(res.status, 400);
+    assert_eq!(res.json::<Value>().unwrap()["field"], json!("chain_id"));
+
+    // rates that are echoed in another form don't change the drips configuration either
+    let mut paid = workstream();
+    paid["receivers"] = json!([{
+        "address": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+        "payment_rate": 150
+    }]);
+    let res = send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&mainnet))
+            .with_json(&paid)
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let mut paid = res.json::<Value>().unwrap();
+    paid["title"] = json!("Renamed");
+    paid["receivers"][0]["payment_rate"] = json!("0.00000000000000015 DAI/second");
+    let res = send(
+        &api,
+        request(
+            Method::Put,
+            &format!(
+                "/api/v1/users/{}/workstreams/{}",
+                user,
+                paid["id"].as_str().unwrap()
+            ),
+        )
+        .with_header("Authorization", &bearer(&optimism))
+        .with_header("If-Match", &etag(&paid))
+        .with_json(&paid)
+        .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+}
+
+#[test]
+fn errors_have_stable_codes() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let error = |res: ApiResponse| -> (u16, Value) {
+        assert_eq!(
+            res.header("Content-Type"),
+            Some(&"application/json".to_owned())
+        );
+        (res.status, res.json::<Value>().unwrap())
+    };
+
+    let (status, body) = error(send(
+        &api,
+        request(Method::Get, "/api/v1/workstreams?state=unknown"),
+    ));
+    assert_eq!(status, 400);
+    assert_eq!(body["code"], json!("validation_error"));
+    assert_eq!(body["field"], json!("state"));
+    assert!(body["message"].is_string());
+
+    let (status, body) = error(send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&token))
+            .with_body("{"),
+    ));
+    assert_eq!(status, 400);
+    assert_eq!(body["code"], json!("validation_error"));
+    assert!(body["field"].is_null());
+
+    let (status, body) = error(send(
+        &api,
+        request(
+            Method::Post,
+            &format!("/api/v1/users/{:?}/workstreams", Address::zero()),
+        )
+        .with_header("Authorization", &bearer(&token))
+        .with_json(&workstream())
+        .unwrap(),
+    ));
+    assert_eq!((status, body["code"].clone()), (401, json!("unauthorized")));
+
+    let (status, body) = error(send(&api, request(Method::Get, "/api/v1/unknown")));
+    assert_eq!((status, body["code"].clone()), (404, json!("not_found")));
+
+    let (status, body) = error(send(&api, request(Method::Delete, "/api/v1/users")));
+    assert_eq!(
+        (status, body["code"].clone()),
+        (405, json!("method_not_allowed"))
+    );
+
+    let created = create_workstream(&api, &user, &token);
+    let (status, body) = error(send(
+        &api,
+        request(
+            Method::Post,
+            &format!(
+                "/api/v1/workstreams/{}/state",
+                created["id"].as_str().unwrap()
+            ),
+        )
+        .with_header("Authorization", &bearer(&token))
+        .with_json(&json!({ "state": "Finished" }))
+        .unwrap(),
+    ));
+    assert_eq!((status, body["code"].clone()), (409, json!("conflict")));
+}
+
+#[test]
+fn payloads_are_validated_field_by_field() {
+    let store = store();
+    let chain = MockChain::default();
+    let mut config = config();
+    config.limits.max_description_length = 20;
+    config.limits.max_receivers = 2;
+    let api = Api::new(&store, &chain, config);
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let fields = |res: &ApiResponse| -> Vec<String> {
+        assert_eq!(res.status, 400, "{}", res.body);
+        let body = res.json::<Value>().unwrap();
+        assert_eq!(body["code"], json!("validation_error"));
+        body["violations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["field"].as_str().unwrap().to_owned())
+            .collect()
+    };
+
+    let mut invalid = workstream();
+    invalid["title"] = json!("  ");
+    invalid["description"] = json!("a description that is too long");
+    invalid["receivers"] = json!([
+        { "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b", "payment_rate": 0 },
+        { "address": "0x7ad046baed02ef99423ef6b53c5940987c5c159b", "payment_rate": 10 },
+        { "address": "0x0000000000000000000000000000000000000000", "payment_rate": 10 }
+    ]);
+    let res = send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&invalid)
+            .unwrap(),
+    );
+    assert_eq!(
+        fields(&res),
+        vec![
+            "title",
+            "description",
+            "receivers",
+            "receivers[0].payment_rate",
+            "receivers[1].address",
+            "receivers[2].address",
+        ]
+    );
+    assert!(block_on(store.list_users()).unwrap().is_empty());
+
+    // a single violation is also reported as the field of the error
+    let created = create_workstream(&api, &user, &token);
+    let path = format!(
+        "/api/v1/users/{}/workstreams/{}",
+        user,
+        created["id"].as_str().unwrap()
+    );
+    let mut update = created.clone();
+    update["title"] = json!("");
+    let res = send(
+        &api,
+        request(Method::Put, &path)
+            .with_header("Authorization", &bearer(&token))
+            .with_header("If-Match", &etag(&created))
+            .with_json(&update)
+            .unwrap(),
+    );
+    assert_eq!(fields(&res), vec!["title"]);
+    assert_eq!(res.json::<Value>().unwrap()["field"], json!("title"));
+
+    // the payment rates are limited to 1 unit of the currency per second by default
+    let mut fast = application();
+    fast["receivers"][0]["payment_rate"] = json!("2 DAI/second");
+    let apply = || {
+        send(
+            &api,
+            request(Method::Post, &format!("{}/applications", path))
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&fast)
+                .unwrap(),
+        )
+    };
+    let res = apply();
+    assert_eq!(fields(&res), vec!["receivers[0].payment_rate"]);
+    assert_eq!(
+        res.json::<Value>().unwrap()["violations"][0]["message"],
+        json!("must stream at most 1 DAI per second")
+    );
+    // unless the currency sets its own limit
+    let mut dai = currency("DAI", 0xd1);
+    dai.max_payment_rate = Some(2);
+    block_on(store.put_currency(&dai)).unwrap();
+    assert_eq!(apply().status, 200);
+}
+
+#[test]
+fn payment_rates_are_converted_exactly() {
+    let store = store();
+    let chain = MockChain::default();
+    let mut config = config();
+    config.limits.max_payment_rate = 100;
+    let api = Api::new(&store, &chain, config);
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let applications = format!(
+        "/api/v1/users/{}/workstreams/{}/applications",
+        user,
+        create_workstream(&api, &user, &token)["id"]
+            .as_str()
+            .unwrap()
+    );
+    let apply = |payment_rate: Value| {
+        let mut application = application();
+        application["receivers"][0]["payment_rate"] = payment_rate;
+        send(
+            &api,
+            request(Method::Post, &applications)
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&application)
+                .unwrap(),
+        )
+    };
+
+    let res = apply(json!("1500 dai/month"));
+    assert_eq!(res.status, 200, "{}", res.body);
+    let applied = res.json::<Value>().unwrap();
+    let monthly = json!({
+        "amount": "1500000000000000000000",
+        "period": "month",
+        "amount_per_second": "578703703703703",
+        "human": "1500 DAI/month"
+    });
+    assert_eq!(applied["receivers"][0]["payment_rate"], monthly);
+
+    // a returned rate can be sent back, its derived fields are computed again
+    let mut edited = applied.clone();
+    edited["receivers"][0]["payment_rate"]["amount_per_second"] = json!("1");
+    let res = send(
+        &api,
+        request(Method::Put, &applications)
+            .with_header("Authorization", &bearer(&token))
+            .with_header("If-Match", &etag(&applied))
+            .with_json(&edited)
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(
+        res.json::<Value>().unwrap()["receivers"][0]["payment_rate"],
+        monthly
+    );
+
+    let rate =
+        |res: ApiResponse| res.json::<Value>().unwrap()["receivers"][0]["payment_rate"].clone();
+    let daily = rate(apply(json!("0.5 DAI/day")));
+    assert_eq!(daily["amount_per_second"], json!("5787037037037"));
+    assert_eq!(daily["human"], json!("0.5 DAI/day"));
+    // rates that overflowed the payment rates without a unit
+    let fast = rate(apply(json!("100 DAI/second")));
+    assert_eq!(fast["amount_per_second"], json!("100000000000000000000"));
+    let legacy = rate(apply(json!(150)));
+    assert_eq!(legacy["amount_per_second"], json!("150"));
+    assert_eq!(legacy["human"], json!("0.00000000000000015 DAI/second"));
+
+    for invalid in &[
+        "1500 USDC/month",
+        "1500 DAI/fortnight",
+        "0.0000000000000000001 DAI/second",
+        "1500 DAI",
+        "1,500 DAI/month",
+        "0 DAI/month",
+    ] {
+        let res = apply(json!(invalid));
+        assert_eq!(res.status, 400, "{}", invalid);
+        assert_eq!(
+            res.json::<Value>().unwrap()["field"],
+            json!("receivers[0].payment_rate"),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn the_indexer_follows_the_funding_of_workstreams() {
+    let store = store();
+    let chain = MockChain::default();
+    chain.set_logs(include_str!("fixtures/drips_logs.json"));
+    let config = config();
+    let api = Api::new(&store, &chain, config.clone());
+    // the first account of anvil, which the drips accounts of the fixture belong to
+    let wallet: LocalWallet = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        .parse()
+        .unwrap();
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let create = |drips_acct: u32| -> String {
+        let mut workstream = workstream();
+        workstream["drips_acct"] = json!(drips_acct);
+        workstream["receivers"] = json!([{
+            "address": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            "payment_rate": 150
+        }]);
+        let res = send(
+            &api,
+            request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&workstream)
+                .unwrap(),
+        );
+        assert_eq!(res.status, 200, "{}", res.body);
+        res.json::<Value>().unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+    // account 0 is funded for years, account 1 ran out of funds in seconds
+    let funded = create(0);
+    let dry = create(1);
+    let state = |id: &str| block_on(store.find_workstream(id)).unwrap().unwrap().state;
+    let events = |id: &str| -> Vec<Value> {
+        block_on(store.get_ledger(id))
+            .unwrap()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| serde_json::to_value(entry).unwrap()["event"].clone())
+            .collect()
+    };
+    let run = || block_on(indexer::run(&store, &chain, &config)).unwrap();
+
+    chain.head.set(102 + CONFIRMATIONS);
+    run();
+    assert_eq!(state(&funded), WorkstreamState::Funded);
+    assert_eq!(state(&dry), WorkstreamState::Open);
+    assert_eq!(
+        events(&funded),
+        vec![json!("DripsUpdated"), json!("Given"), json!("Collected")]
+    );
+    // the receiver collects from both accounts, the logs of other accounts and DripsHubs are
+    // ignored
+    assert_eq!(
+        events(&dry),
+        vec![json!("DripsUpdated"), json!("Collected")]
+    );
+
+    // blocks that are read again aren't recorded twice
+    block_on(store.put_indexed_block(1, &Address::repeat_byte(0xd1), 0)).unwrap();
+    run();
+    assert_eq!(events(&funded).len(), 3);
+
+    // the creator withdraws all the funds of account 0
+    chain.head.set(105 + CONFIRMATIONS);
+    run();
+    assert_eq!(state(&funded), WorkstreamState::Finished);
+    assert_eq!(events(&funded).len(), 4);
+    assert_eq!(state(&dry), WorkstreamState::Open);
+    assert_eq!(
+        block_on(store.get_indexed_block(1, &Address::repeat_byte(0xd1))).unwrap(),
+        Some(105)
+    );
+}
+
+#[test]
+fn the_indexer_backfills_workstreams_created_after_their_funding() {
+    let store = store();
+    let chain = MockChain::default();
+    chain.set_logs(include_str!("fixtures/drips_logs.json"));
+    let config = config();
+    let api = Api::new(&store, &chain, config.clone());
+    let run = || block_on(indexer::run(&store, &chain, &config)).unwrap();
+
+    // the DripsHub is indexed past the funding of account 0 before the workstream exists
+    chain.head.set(MAX_BLOCKS + 102 + CONFIRMATIONS);
+    run();
+    run();
+    assert_eq!(
+        block_on(store.get_indexed_block(1, &Address::repeat_byte(0xd1))).unwrap(),
+        Some(MAX_BLOCKS + 102)
+    );
+
+    let wallet: LocalWallet = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        .parse()
+        .unwrap();
+    let token = login(&api, &wallet);
+    let mut workstream = workstream();
+    workstream["receivers"] = json!([{
+        "address": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+        "payment_rate": 150
+    }]);
+    let res = send(
+        &api,
+        request(
+            Method::Post,
+            &format!("/api/v1/users/{}/workstreams", address(&wallet)),
+        )
+        .with_header("Authorization", &bearer(&token))
+        .with_json(&workstream)
+        .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let id = res.json::<Value>().unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    // the funding is backfilled, but the workstream isn't followed until its ledger caught up
+    run();
+    let ledger = block_on(store.get_ledger(&id)).unwrap().unwrap();
+    assert_eq!(ledger.backfill_from, Some(MAX_BLOCKS));
+    assert!(ledger.drips_state().is_some());
+    assert_eq!(
+        block_on(store.find_workstream(&id)).unwrap().unwrap().state,
+        WorkstreamState::Open
+    );
+
+    run();
+    let mut ledger = block_on(store.get_ledger(&id)).unwrap().unwrap();
+    assert_eq!(ledger.backfill_from, None);
+    assert_eq!(
+        ledger
+            .entries
+            .iter()
+            .map(|entry| serde_json::to_value(entry).unwrap()["event"].clone())
+            .collect::<Vec<Value>>(),
+        vec![json!("DripsUpdated"), json!("Given"), json!("Collected")]
+    );
+    assert_eq!(
+        block_on(store.find_workstream(&id)).unwrap().unwrap().state,
+        WorkstreamState::Funded
+    );
+
+    // the events are kept in the order of the chain, whatever the order they are recorded in
+    let mut earlier = ledger.entries[2].clone();
+    earlier.block_number = 100;
+    assert!(ledger.record(&earlier));
+    assert!(!ledger.record(&earlier));
+    assert_eq!(ledger.entries[0], earlier);
+}
+
+#[test]
+fn workstream_funding_is_read_from_the_drips_hub() {
+    let store = store();
+    let chain = MockChain::default();
+    chain.set_logs(include_str!("fixtures/drips_logs.json"));
+    chain.head.set(105);
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let id = create_workstream(&api, &user, &token)["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let funding = |path: &str| -> Value {
+        let res = send(&api, request(Method::Get, path));
+        assert_eq!(res.status, 200, "{}", res.body);
+        res.json::<Value>().unwrap()
+    };
+
+    // the drips account has not been configured on-chain yet
+    let unfunded = funding(&format!("/api/v1/workstreams/{}/funding", id));
+    assert_eq!(unfunded["workstream_id"], json!(id));
+    assert_eq!(unfunded["balance"], json!("0"));
+    assert_eq!(unfunded["amount_per_second"], json!("0"));
+    assert_eq!(unfunded["ends_at"], Value::Null);
+    assert_eq!(unfunded["receivers"], json!([]));
+
+    let update_time = Utc::now().timestamp().unsigned_abs() - 100;
+    let receiver: Address = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8"
+        .parse()
+        .unwrap();
+    let other = Address::repeat_byte(0x3c);
+    chain.set_drips(
+        wallet.address(),
+        0,
+        DripsState {
+            update_time,
+            balance: U256::from(1_000_000u64),
+            receivers: vec![(receiver, U256::from(100)), (other, U256::from(50))],
+        },
+    );
+    let funded = funding(&format!(
+        "/api/v1/users/{}/workstreams/{}/funding",
+        user, id
+    ));
+    assert_eq!(
+        funding(&format!("/api/v1/workstreams/{}/funding", id))["ends_at"],
+        funded["ends_at"]
+    );
+    // 100 seconds at 150 per second, give or take the seconds of the test
+    let balance = U256::from_dec_str(funded["balance"].as_str().unwrap()).unwrap();
+    assert!(balance <= U256::from(985_000u64) && balance >= U256::from(984_000u64));
+    assert_eq!(funded["amount_per_second"], json!("150"));
+    assert_eq!(
+        funded["ends_at"],
+        json!(Utc
+            .timestamp(update_time as i64 + 6_666, 0)
+            .to_rfc3339_opts(SecondsFormat::Millis, true))
+    );
+    // the receivers collected from all of their senders in the fixture
+    assert_eq!(
+        funded["receivers"],
+        json!([
+            {
+                "address": format!("{:?}", receiver),
+                "amount_per_second": "100",
+                "total_collected": "100000",
+            },
+            {
+                "address": format!("{:?}", other),
+                "amount_per_second": "50",
+                "total_collected": "0",
+            },
+        ])
+    );
+
+    assert_eq!(
+        send(
+            &api,
+            request(Method::Get, "/api/v1/workstreams/unknown/funding")
+        )
+        .status,
+        404
+    );
+    assert_eq!(
+        send(
+            &api,
+            request(Method::Post, &format!("/api/v1/workstreams/{}/funding", id))
+        )
+        .status,
+        405
+    );
+}
+
+#[test]
+fn dates_are_parsed_leniently_and_returned_in_rfc3339() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let create = |starting_at: &str, ending_at: &str| {
+        let mut workstream = workstream();
+        workstream["starting_at"] = json!(starting_at);
+        workstream["ending_at"] = json!(ending_at);
+        send(
+            &api,
+            request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&workstream)
+                .unwrap(),
+        )
+    };
+
+    let res = create("March 5, 2099 12:17:31 GMT", "2099-03-10");
+    assert_eq!(res.status, 200, "{}", res.body);
+    let created = res.json::<Value>().unwrap();
+    assert_eq!(created["starting_at"], json!("2099-03-05T12:17:31.000Z"));
+    assert_eq!(created["ending_at"], json!("2099-03-10T00:00:00.000Z"));
+    let created_at = created["created_at"].as_str().unwrap();
+    assert!(created_at.ends_with('Z'));
+    assert!(DateTime::parse_from_rfc3339(created_at).is_ok());
+
+    let violations = |res: ApiResponse| -> Value {
+        assert_eq!(res.status, 400, "{}", res.body);
+        res.json::<Value>().unwrap()["violations"].clone()
+    };
+    assert_eq!(
+        violations(create("2099-03-10", "Thu, 05 Mar 2099 12:17:31 +0000")),
+        json!([{ "field": "ending_at", "message": "must be after starting_at" }])
+    );
+    assert_eq!(
+        violations(create("2001-03-10", "2099-03-10T00:00:00Z"))[0]["field"],
+        json!("starting_at")
+    );
+    assert_eq!(create("soon", "2099-03-10").status, 400);
+
+    let res = send(
+        &api,
+        request(
+            Method::Get,
+            "/api/v1/workstreams?starting_after=2099-03-01&ending_before=2099-03-10T00:00:00Z",
+        ),
+    );
+    let page = res.json::<Value>().unwrap();
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert_eq!(page["items"][0]["id"], created["id"]);
+
+    // the format of JavaScript's `Date.prototype.toString`
+    let parsed =
+        dates::parse_date("Wed Mar 02 2022 12:46:38 GMT+0000 (Coordinated Universal Time)");
+    assert_eq!(
+        parsed.map(|date| dates::format_date(&date)),
+        Some("2022-03-02T12:46:38.000Z".to_owned())
+    );
+}
+
+#[test]
+fn legacy_users_are_read_with_their_workstreams() {
+    let creator = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+    let workstreams =
+        store::legacy_workstreams(creator, include_str!("fixtures/legacy_user.json")).unwrap();
+    let workstreams: Vec<Value> = workstreams
+        .iter()
+        .map(|workstream| serde_json::to_value(workstream).unwrap())
+        .collect();
+    assert_eq!(workstreams.len(), 2);
+
+    assert_eq!(
+        workstreams[0]["id"],
+        json!("3b9d2a41-7e0c-4f18-8d6b-5c2e9a0f4b13")
+    );
+    assert_eq!(workstreams[0]["creator"], json!(creator));
+    assert_eq!(
+        workstreams[0]["created_at"],
+        json!("2022-03-03T08:05:12.000Z")
+    );
+    assert_eq!(workstreams[0]["state"], json!("Funded"));
+
+    assert_eq!(
+        workstreams[1]["id"],
+        json!("8c1f6c9e-4b7a-4d5e-9f43-2a6f0c1d7e55")
+    );
+    assert_eq!(workstreams[1]["creator"], json!(creator));
+    assert_eq!(
+        workstreams[1]["created_at"],
+        json!("2022-03-02T12:46:38.000Z")
+    );
+    assert_eq!(
+        workstreams[1]["starting_at"],
+        json!("2022-03-07T00:00:00.000Z")
+    );
+    assert_eq!(workstreams[1]["payment_currency"], json!("DAI"));
+    assert_eq!(workstreams[1]["chain_id"], json!(1));
+    assert_eq!(workstreams[1]["receivers"], json!([]));
+    assert_eq!(workstreams[1]["version"], json!(0));
+}
+
+#[test]
+fn workstream_lifecycle_is_enforced() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let id = create_workstream(&api, &user, &token)["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let transition = |state: &str| {
+        send(
+            &api,
+            request(
+                Method::Post,
+                &format!("/api/v1/users/{}/workstreams/{}/state", user, id),
+            )
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&json!({ "state": state }))
+            .unwrap(),
+        )
+    };
+
+    assert_eq!(transition("Finished").status, 409);
+    // the drips account has not been funded on-chain yet
+    assert_eq!(transition("Funded").status, 409);
+    // or it can't be read
+    chain.down.set(true);
+    assert_eq!(transition("Funded").status, 502);
+    chain.down.set(false);
+    chain.set_drips(
+        wallet.address(),
+        0,
+        DripsState {
+            update_time: Utc::now().timestamp() as u64,
+            balance: U256::from(1_000_000),
+            receivers: vec![],
+        },
+    );
+    assert_eq!(transition("Funded").status, 200);
+    assert_eq!(transition("Open").status, 409);
+    let res = transition("Finished");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.json::<Value>().unwrap()["state"], json!("Finished"));
+    assert_eq!(transition("Cancelled").status, 409);
+}
+
+#[test]
+fn workstreams_are_listed_from_the_indexes() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let alice = LocalWallet::new(&mut rand::thread_rng());
+    let bob = LocalWallet::new(&mut rand::thread_rng());
+    let alice_token = login(&api, &alice);
+    let bob_token = login(&api, &bob);
+    let cancelled = create_workstream(&api, &address(&alice), &alice_token);
+    create_workstream(&api, &address(&alice), &alice_token);
+    let deleted = create_workstream(&api, &address(&bob), &bob_token);
+    let res = send(
+        &api,
+        request(
+            Method::Post,
+            &format!(
+                "/api/v1/users/{}/workstreams/{}/state",
+                address(&alice),
+                cancelled["id"].as_str().unwrap()
+            ),
+        )
+        .with_header("Authorization", &bearer(&alice_token))
+        .with_json(&json!({ "state": "Cancelled" }))
+        .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let res = send(
+        &api,
+        request(
+            Method::Delete,
+            &format!(
+                "/api/v1/users/{}/workstreams/{}",
+                address(&bob),
+                deleted["id"].as_str().unwrap()
+            ),
+        )
+        .with_header("Authorization", &bearer(&bob_token))
+        .with_header("If-Match", &etag(&deleted)),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+
+    let list = |query: &str| {
+        let res = send(
+            &api,
+            request(Method::Get, &format!("/api/v1/workstreams{}", query)),
+        );
+        assert_eq!(res.status, 200, "{}", res.body);
+        res.json::<Value>().unwrap()
+    };
+    let ids = |page: &Value| -> Vec<String> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["id"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(ids(&list("")).len(), 2);
+    assert_eq!(ids(&list("?state=open")).len(), 1);
+    assert_eq!(
+        ids(&list("?state=cancelled")),
+        vec![cancelled["id"].as_str().unwrap().to_owned()]
+    );
+    assert_eq!(
+        ids(&list(&format!("?creator={}", address(&alice)))).len(),
+        2
+    );
+    assert!(ids(&list(&format!("?creator={}", address(&bob)))).is_empty());
+    assert_eq!(
+        ids(&list(&format!("?creator={}&state=open", address(&alice)))).len(),
+        1
+    );
+    assert_eq!(ids(&list("?payment_currency=dai")).len(), 2);
+
+    // pages are linked with their cursors
+    let first = list("?limit=1");
+    assert_eq!(ids(&first).len(), 1);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let second = list(&format!("?limit=1&cursor={}", cursor));
+    assert_eq!(ids(&second).len(), 1);
+    assert_ne!(ids(&first), ids(&second));
+    assert!(second["next_cursor"].is_null());
+
+    let res = send(&api, request(Method::Get, "/api/v1/workstreams?limit=0"));
+    assert_eq!(res.status, 400);
+}
+
+#[test]
+fn collections_are_sorted_and_paginated() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    for title in &["Bravo", "alpha", "Charlie"] {
+        let mut workstream = workstream();
+        workstream["title"] = json!(title);
+        let res = send(
+            &api,
+            request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+                .with_header("Authorization", &bearer(&token))
+                .with_json(&workstream)
+                .unwrap(),
+        );
+        assert_eq!(res.status, 200, "{}", res.body);
+    }
+    let list = |path: &str| {
+        let res = send(&api, request(Method::Get, path));
+        assert_eq!(res.status, 200, "{}", res.body);
+        res.json::<Value>().unwrap()
+    };
+    let titles = |page: &Value| -> Vec<String> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+
+    for path in &[
+        format!("/api/v1/users/{}/workstreams", user),
+        "/api/v1/workstreams".to_owned(),
+    ] {
+        let first = list(&format!("{}?sort=title&order=desc&limit=2", path));
+        assert_eq!(titles(&first), vec!["Charlie", "Bravo"]);
+        let second = list(&format!(
+            "{}?sort=title&order=desc&limit=2&cursor={}",
+            path,
+            first["next_cursor"].as_str().unwrap()
+        ));
+        assert_eq!(titles(&second), vec!["alpha"]);
+        assert!(second["next_cursor"].is_null());
+    }
+
+    let users = list("/api/v1/users");
+    assert_eq!(users["items"], json!([user]));
+    for query in &["sort=updated_at", "order=up", "limit=5000", "cursor=zz"] {
+        let res = send(
+            &api,
+            request(Method::Get, &format!("/api/v1/workstreams?{}", query)),
+        );
+        assert_eq!(res.status, 400, "{}", query);
+    }
+    let res = send(&api, request(Method::Get, "/api/v1/users?sort=title"));
+    assert_eq!(res.status, 400);
+}
+
+#[test]
+fn workstreams_are_filtered() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&owner);
+    let token = login(&api, &owner);
+    let grant = create_workstream(&api, &user, &token);
+    let mut role = workstream();
+    role["title"] = json!("Protocol maintainer");
+    role["wtype"] = json!("Role");
+    role["description"] = json!("Maintain the seed nodes");
+    let res = send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&role)
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let role: Value = res.json().unwrap();
+
+    // accepting an application adds its receivers to the workstream
+    let applicant = LocalWallet::new(&mut rand::thread_rng());
+    let applications = format!(
+        "/api/v1/workstreams/{}/applications",
+        grant["id"].as_str().unwrap()
+    );
+    let res = send(
+        &api,
+        request(Method::Post, &applications)
+            .with_header("Authorization", &bearer(&login(&api, &applicant)))
+            .with_json(&application())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let applied: Value = res.json().unwrap();
+    let res = send(
+        &api,
+        request(
+            Method::Post,
+            &format!(
+                "{}/{}/accept",
+                applications,
+                applied["id"].as_str().unwrap()
+            ),
+        )
+        .with_header("Authorization", &bearer(&token)),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+
+    let ids = |query: &str| -> Vec<Value> {
+        let res = send(
+            &api,
+            request(Method::Get, &format!("/api/v1/workstreams?{}", query)),
+        );
+        assert_eq!(res.status, 200, "{}", res.body);
+        res.json::<Value>().unwrap()["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["id"].clone())
+            .collect()
+    };
+    assert_eq!(ids("wtype=role"), vec![role["id"].clone()]);
+    assert_eq!(ids("q=SEED"), vec![role["id"].clone()]);
+    assert_eq!(ids("q=drips&wtype=grant"), vec![grant["id"].clone()]);
+    assert_eq!(
+        ids("receiver=0x7ad046baed02ef99423ef6b53c5940987c5c159b"),
+        vec![grant["id"].clone()]
+    );
+    // workstreams without dates are outside of every date range
+    assert!(ids("starting_after=2022-01-01").is_empty());
+    assert_eq!(
+        ids(&format!("creator={}&payment_currency=dai", user)).len(),
+        2
+    );
+
+    for query in &[
+        "wtype=job",
+        "starting_after=yesterday",
+        "starting_after=2022-03-02&starting_before=2022-03-01",
+        "receiver=0x1",
+        "colour=red",
+    ] {
+        let res = send(
+            &api,
+            request(Method::Get, &format!("/api/v1/workstreams?{}", query)),
+        );
+        assert_eq!(res.status, 400, "{}", query);
+    }
+}
+
+#[test]
+fn workstreams_are_reachable_by_id() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let owner_token = login(&api, &owner);
+    let created = create_workstream(&api, &address(&owner), &owner_token);
+    let path = format!("/api/v1/workstreams/{}", created["id"].as_str().unwrap());
+
+    let res = send(&api, request(Method::Get, &path));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json::<Value>().unwrap(), created);
+
+    let applicant = LocalWallet::new(&mut rand::thread_rng());
+    let applicant_token = login(&api, &applicant);
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/applications", path))
+            .with_header("Authorization", &bearer(&applicant_token))
+            .with_json(&application())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let applied: Value = res.json().unwrap();
+    let res = send(
+        &api,
+        request(
+            Method::Get,
+            &format!("{}/applications/{}", path, applied["id"].as_str().unwrap()),
+        ),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json::<Value>().unwrap(), applied);
+
+    // only the creator can change the workstream, whatever the route
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/state", path))
+            .with_header("Authorization", &bearer(&applicant_token))
+            .with_json(&json!({ "state": "Cancelled" }))
+            .unwrap(),
+    );
+    assert_eq!(res.status, 401);
+    let res = send(
+        &api,
+        request(Method::Delete, &path)
+            .with_header("Authorization", &bearer(&owner_token))
+            .with_header("If-Match", &etag(&created)),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(send(&api, request(Method::Get, &path)).status, 404);
+}
+
+#[test]
+fn only_the_workstream_creator_reviews_applications() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let applicant = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&owner);
+    let owner_token = login(&api, &owner);
+    let applicant_token = login(&api, &applicant);
+    let id = create_workstream(&api, &user, &owner_token)["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let applications = format!("/api/v1/users/{}/workstreams/{}/applications", user, id);
+    let res = send(
+        &api,
+        request(Method::Post, &applications)
+            .with_header("Authorization", &bearer(&applicant_token))
+            .with_json(&application())
+            .unwrap(),
+    );
+    let mut applied: Value = res.json().unwrap();
+    let application_path = format!("{}/{}", applications, applied["id"].as_str().unwrap());
+
+    // the applicant can't accept their own application, nor mark it as accepted
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/accept", application_path))
+            .with_header("Authorization", &bearer(&applicant_token)),
+    );
+    assert_eq!(res.status, 401);
+    applied["state"] = json!("Accepted");
+    let res = send(
+        &api,
+        request(Method::Put, &applications)
+            .with_header("Authorization", &bearer(&applicant_token))
+            .with_header("If-Match", &etag(&applied))
+            .with_json(&applied)
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json::<Value>().unwrap()["state"], json!("Pending"));
+
+    // the workstream creator can't edit or withdraw the application
+    let res = send(
+        &api,
+        request(Method::Put, &applications)
+            .with_header("Authorization", &bearer(&owner_token))
+            .with_json(&applied)
+            .unwrap(),
+    );
+    assert_eq!(res.status, 403);
+    let res = send(
+        &api,
+        request(Method::Delete, &application_path)
+            .with_header("Authorization", &bearer(&owner_token)),
+    );
+    assert_eq!(res.status, 403);
+
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/accept", application_path))
+            .with_header("Authorization", &bearer(&owner_token)),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json::<Value>().unwrap()["state"], json!("Accepted"));
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/reject", application_path))
+            .with_header("Authorization", &bearer(&owner_token)),
+    );
+    assert_eq!(res.status, 409);
+
+    let res = send(
+        &api,
+        request(
+            Method::Get,
+            &format!("/api/v1/users/{}/workstreams/{}", user, id),
+        ),
+    );
+    assert_eq!(
+        res.json::<Value>().unwrap()["receivers"],
+        applied["receivers"]
+    );
+}
+
+#[test]
+fn accepted_applications_respect_the_maximum_of_receivers() {
+    let store = store();
+    let chain = MockChain::default();
+    let mut config = config();
+    config.limits.max_receivers = 2;
+    let api = Api::new(&store, &chain, config);
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&owner);
+    let owner_token = login(&api, &owner);
+    let id = create_workstream(&api, &user, &owner_token)["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let applications = format!("/api/v1/users/{}/workstreams/{}/applications", user, id);
+    let accept = |receiver: &str| {
+        let applicant = LocalWallet::new(&mut rand::thread_rng());
+        let mut application = application();
+        application["receivers"][0]["address"] = json!(receiver);
+        let res = send(
+            &api,
+            request(Method::Post, &applications)
+                .with_header("Authorization", &bearer(&login(&api, &applicant)))
+                .with_json(&application)
+                .unwrap(),
+        );
+        assert_eq!(res.status, 200, "{}", res.body);
+        let applied: Value = res.json().unwrap();
+        send(
+            &api,
+            request(
+                Method::Post,
+                &format!(
+                    "{}/{}/accept",
+                    applications,
+                    applied["id"].as_str().unwrap()
+                ),
+            )
+            .with_header("Authorization", &bearer(&owner_token)),
+        )
+    };
+    let receivers = || {
+        let res = send(
+            &api,
+            request(
+                Method::Get,
+                &format!("/api/v1/users/{}/workstreams/{}", user, id),
+            ),
+        );
+        res.json::<Value>().unwrap()["receivers"]
+            .as_array()
+            .unwrap()
+            .len()
+    };
+
+    assert_eq!(
+        accept("0x70997970c51812dc3a010c7d01b50e0d17dc79c8").status,
+        200
+    );
+    let res = accept("0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(receivers(), 2);
+
+    // a receiver of the workstream is replaced, so it doesn't count twice
+    assert_eq!(
+        accept("0x70997970c51812dc3a010c7d01b50e0d17dc79c8").status,
+        200
+    );
+    let res = accept("0x90f79bf6eb2c4f870365e785982e1f101e93b906");
+    assert_eq!(res.status, 400, "{}", res.body);
+    assert_eq!(res.json::<Value>().unwrap()["field"], json!("receivers"));
+    assert_eq!(receivers(), 2);
+}
+
+#[test]
+fn stale_writes_are_rejected() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let created = create_workstream(&api, &user, &token);
+    let path = format!(
+        "/api/v1/users/{}/workstreams/{}",
+        user,
+        created["id"].as_str().unwrap()
+    );
+    let res = send(&api, request(Method::Get, &path));
+    assert_eq!(res.header("ETag"), Some(&"\"1\"".to_owned()));
+
+    let update = |title: &str, if_match: Option<&str>| {
+        let mut workstream = created.clone();
+        workstream["title"] = json!(title);
+        let mut req = request(Method::Put, &path)
+            .with_header("Authorization", &bearer(&token))
+            .with_json(&workstream)
+            .unwrap();
+        if let Some(if_match) = if_match {
+            req = req.with_header("If-Match", if_match);
+        }
+        send(&api, req)
+    };
+    assert_eq!(update("first", None).status, 428);
+    let res = update("first", Some("\"1\""));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.header("ETag"), Some(&"\"2\"".to_owned()));
+    // a client that read the first version can't overwrite the second one
+    assert_eq!(update("second", Some("\"1\"")).status, 412);
+    let res = send(&api, request(Method::Get, &path));
+    assert_eq!(res.json::<Value>().unwrap()["state"], json!("Cancelled"));
+    assert_eq!(update("second", Some("\"2\"")).status, 200);
+
+    let delete = |if_match: &str| {
+        send(
+            &api,
+            request(Method::Delete, &path)
+                .with_header("Authorization", &bearer(&token))
+                .with_header("If-Match", if_match),
+        )
+    };
+    assert_eq!(delete("\"2\"").status, 412);
+    assert_eq!(delete("\"3\"").status, 200);
+}
+
+#[test]
+fn concurrent_writes_go_through_the_aggregate() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let created = create_workstream(&api, &user, &token);
+    let id = created["id"].as_str().unwrap();
+    let res = send(
+        &api,
+        request(
+            Method::Post,
+            &format!("/api/v1/workstreams/{}/applications", id),
+        )
+        .with_header("Authorization", &bearer(&token))
+        .with_json(&application())
+        .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let applied: Application = res.json().unwrap();
+
+    // two writes that are based on the same read: only the first one is applied
+    let read = block_on(store.find_workstream(id)).unwrap().unwrap();
+    let (mut first, mut second) = (read.clone(), read);
+    first.state = WorkstreamState::Cancelled;
+    first.version += 1;
+    second.state = WorkstreamState::Funded;
+    second.version += 1;
+    assert!(block_on(store.put_workstream(&first)).unwrap());
+    assert!(!block_on(store.put_workstream(&second)).unwrap());
+    let res = send(
+        &api,
+        request(Method::Get, &format!("/api/v1/workstreams/{}", id)),
+    );
+    assert_eq!(res.json::<Value>().unwrap()["state"], json!("Cancelled"));
+
+    // an acceptance that conflicts on the application doesn't change the workstream either
+    let mut stale = applied.clone();
+    stale.version += 2;
+    first.version += 1;
+    assert!(!block_on(store.accept_application(&first, &stale)).unwrap());
+    assert_eq!(
+        block_on(store.find_workstream(id))
+            .unwrap()
+            .unwrap()
+            .version,
+        first.version - 1
+    );
+
+    // applications can't outlive their workstream
+    let mut orphan = applied.clone();
+    orphan.workstream_id = "unknown".to_owned();
+    assert!(!block_on(store.put_application(&orphan)).unwrap());
+    // a delete that is based on a stale version is rejected too
+    assert!(
+        block_on(store.delete_workstream(&first.creator, id, first.version))
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        block_on(store.delete_workstream(&first.creator, id, first.version - 1))
+            .unwrap()
+            .is_some()
+    );
+    assert!(block_on(store.get_application(id, &applied.id))
+        .unwrap()
+        .is_none());
+    assert!(block_on(store.list_applications(id)).unwrap().is_none());
+    assert!(block_on(store.list_users()).unwrap().is_empty());
+}
+
+#[test]
+fn the_aggregate_only_accepts_the_next_version() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let token = login(&api, &wallet);
+    let created = create_workstream(&api, &address(&wallet), &token);
+    let mut workstream: Workstream = serde_json::from_value(created).unwrap();
+    workstream.version = 2;
+    let mut aggregate = WorkstreamAggregate::default();
+    let put = |workstream: &Workstream| Command::Put {
+        workstream: Some(workstream.clone()),
+        applications: vec![],
+    };
+    assert!(!aggregate.apply(put(&workstream)).applied);
+    workstream.version = 1;
+    let outcome = aggregate.apply(put(&workstream));
+    assert!(outcome.applied);
+    assert_eq!(outcome.previous, WorkstreamAggregate::default());
+    assert_eq!(outcome.current.workstream, Some(workstream.clone()));
+    assert!(!aggregate.apply(put(&workstream)).applied);
+    assert!(
+        !aggregate
+            .apply(Command::DeleteWorkstream { version: 2 })
+            .applied
+    );
+    assert!(
+        aggregate
+            .apply(Command::DeleteWorkstream { version: 1 })
+            .applied
+    );
+    assert!(
+        !aggregate
+            .apply(Command::DeleteWorkstream { version: 1 })
+            .applied
+    );
+}
+
+#[test]
+fn authorization_requires_a_fresh_nonce_and_the_api_domain() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+
+    // a nonce that was never issued by the API
+    let message = siwe_message(&wallet, "localhost:8787", "zPPtgK5pMVHnnr8Co");
+    assert_eq!(authorize(&api, &wallet, &message).status, 401);
+
+    // a message that was signed for another website
+    let message = siwe_message(&wallet, "example.com", &nonce(&api));
+    assert_eq!(authorize(&api, &wallet, &message).status, 401);
+
+    // a message can't be replayed
+    let message = siwe_message(&wallet, "localhost:8787", &nonce(&api));
+    assert_eq!(authorize(&api, &wallet, &message).status, 200);
+    assert_eq!(authorize(&api, &wallet, &message).status, 401);
+}
+
+#[test]
+fn smart_contract_wallets_sign_in_with_eip1271() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let owner = LocalWallet::new(&mut rand::thread_rng());
+    let safe = Address::repeat_byte(0x5a);
+    let message = siwe_message(&owner, "localhost:8787", &nonce(&api)).replace(
+        &to_checksum(&owner.address(), None),
+        &to_checksum(&safe, None),
+    );
+
+    // the owner's signature doesn't recover to the address of the wallet
+    assert_eq!(authorize(&api, &owner, &message).status, 401);
+
+    chain.set_wallet(safe, owner.address());
+    let message = siwe_message(&owner, "localhost:8787", &nonce(&api)).replace(
+        &to_checksum(&owner.address(), None),
+        &to_checksum(&safe, None),
+    );
+    let res = authorize(&api, &owner, &message);
+    assert_eq!(res.status, 200, "{}", res.body);
+    let token = cookies::parse(res.header("Set-Cookie").unwrap())[cookies::SESSION_COOKIE].clone();
+    let res = send(
+        &api,
+        request(Method::Get, &format!("/api/v1/users/{:?}/sessions", safe))
+            .with_header("Authorization", &bearer(&token)),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+
+    // a signature by anyone else is rejected by the wallet
+    let stranger = LocalWallet::new(&mut rand::thread_rng());
+    let message = siwe_message(&owner, "localhost:8787", &nonce(&api)).replace(
+        &to_checksum(&owner.address(), None),
+        &to_checksum(&safe, None),
+    );
+    assert_eq!(authorize(&api, &stranger, &message).status, 401);
+}
+
+#[test]
+fn tokens_can_be_revoked() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let sessions = format!("/api/v1/users/{}/sessions", address(&wallet));
+    let first = login(&api, &wallet);
+    let second = login(&api, &wallet);
+    let third = login(&api, &wallet);
+
+    let res = send(
+        &api,
+        request(Method::Get, &sessions).with_header("Authorization", &bearer(&first)),
+    );
+    assert_eq!(res.status, 200);
+    assert_eq!(res.json::<Vec<Value>>().unwrap().len(), 3);
+
+    let res = send(
+        &api,
+        request(Method::Post, "/api/v1/logout").with_header("Authorization", &bearer(&first)),
+    );
+    assert_eq!(res.status, 200);
+    let res = send(
+        &api,
+        request(Method::Get, &sessions).with_header("Authorization", &bearer(&first)),
+    );
+    assert_eq!(res.status, 401);
+
+    let res = send(
+        &api,
+        request(Method::Delete, &sessions).with_header("Authorization", &bearer(&second)),
+    );
+    assert_eq!(res.status, 200);
+    for token in &[second, third] {
+        let res = send(
+            &api,
+            request(Method::Get, &sessions).with_header("Authorization", &bearer(token)),
+        );
+        assert_eq!(res.status, 401);
+    }
+}
+
+#[test]
+fn the_session_cookie_expires_with_the_authorization() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let message = siwe_message(&wallet, "localhost:8787", &nonce(&api));
+    let res = authorize(&api, &wallet, &message);
+    assert_eq!(res.status, 200, "{}", res.body);
+    let set_cookie = res.header("Set-Cookie").unwrap().clone();
+    let expiration = message.rsplit("Expiration Time: ").next().unwrap();
+    let expiration = chrono::DateTime::parse_from_rfc3339(expiration).unwrap();
+    assert!(set_cookie.contains(&format!(
+        "Expires={}",
+        expiration.format("%a, %d %b %Y %H:%M:%S GMT")
+    )));
+    let max_age: i64 = cookies::parse(&set_cookie)["Max-Age"].parse().unwrap();
+    assert!((3590..=3600).contains(&max_age));
+    assert!(set_cookie.contains("SameSite=Lax"));
+
+    // browsers send the cookie back in the Cookie header
+    let token = cookies::parse(&set_cookie)[cookies::SESSION_COOKIE].clone();
+    let sessions = format!("/api/v1/users/{}/sessions", address(&wallet));
+    let cookie = format!("theme=dark; {}={}", cookies::SESSION_COOKIE, token);
+    let res = send(
+        &api,
+        request(Method::Get, &sessions).with_header("Cookie", &cookie),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+
+    let res = send(
+        &api,
+        request(Method::Post, "/api/v1/logout").with_header("Cookie", &cookie),
+    );
+    assert_eq!(res.status, 200);
+    assert!(res.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+    let res = send(
+        &api,
+        request(Method::Get, &sessions).with_header("Cookie", &cookie),
+    );
+    assert_eq!(res.status, 401);
+}
+
+#[test]
+fn scoped_tokens_only_access_their_resources() {
+    let store = store();
+    let chain = MockChain::default();
+    let api = Api::new(&store, &chain, config());
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let user = address(&wallet);
+    let token = login(&api, &wallet);
+    let first = create_workstream(&api, &user, &token);
+    let second = create_workstream(&api, &user, &token);
+    let path = |workstream: &Value| {
+        format!(
+            "/api/v1/users/{}/workstreams/{}",
+            user,
+            workstream["id"].as_str().unwrap()
+        )
+    };
+
+    let message = format!(
+        "{}\nResources:\n- {}{}",
+        siwe_message(&wallet, "localhost:8787", &nonce(&api)),
+        HOST,
+        path(&first)
+    );
+    let res = authorize(&api, &wallet, &message);
+    assert_eq!(res.status, 200, "{}", res.body);
+    let scoped = res
+        .header("Set-cookie")
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .trim_start_matches("SIWE-AUTH=")
+        .to_owned();
+
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/state", path(&first)))
+            .with_header("Authorization", &bearer(&scoped))
+            .with_json(&json!({ "state": "Cancelled" }))
+            .unwrap(),
+    );
+    assert_eq!(res.status, 200, "{}", res.body);
+    let res = send(
+        &api,
+        request(Method::Post, &format!("{}/state", path(&second)))
+            .with_header("Authorization", &bearer(&scoped))
+            .with_json(&json!({ "state": "Cancelled" }))
+            .unwrap(),
+    );
+    assert_eq!(res.status, 401);
+    let res = send(
+        &api,
+        request(Method::Post, &format!("/api/v1/users/{}/workstreams", user))
+            .with_header("Authorization", &bearer(&scoped))
+            .with_json(&workstream())
+            .unwrap(),
+    );
+    assert_eq!(res.status, 401);
+}
+
+#[test]
+fn authorizations_are_only_active_within_their_validity_window() {
+    let now = Utc::now();
+    let authorization = |not_before: chrono::DateTime<Utc>, expiration: chrono::DateTime<Utc>| {
+        serde_json::from_value::<Authorization>(json!({
+            "resources": [],
+            "issued_at": now.to_rfc3339(),
+            "not_before": not_before.to_rfc3339(),
+            "expiration_time": expiration.to_rfc3339(),
+            "address": Address::zero(),
+        }))
+        .unwrap()
+    };
+    let hour = Duration::hours(1);
+    assert!(authorization(now - hour, now + hour).is_active(now));
+    assert!(!authorization(now + hour, now + hour * 2).is_active(now));
+    assert!(!authorization(now - hour * 2, now - hour).is_active(now));
+}
+
+/// A devnet, e.g `anvil`, for the tests of `RpcChain`. They are ignored, and run with
+/// `cargo test -- --ignored` once `DEVNET_RPC_URL` is set to the URL of the node of the devnet.
+/// The chain id of the devnet is `DEVNET_CHAIN_ID` (the 31337 of `anvil` by default).
+struct Devnet {
+    chain_id: u64,
+    chain: RpcChain,
+    provider: Provider<Http>,
+}
+
+fn devnet() -> Option<Devnet> {
+    let url = std::env::var("DEVNET_RPC_URL").ok()?;
+    let chain_id = std::env::var("DEVNET_CHAIN_ID")
+        .map(|chain_id| chain_id.parse().unwrap())
+        .unwrap_or(31337);
+    Some(Devnet {
+        chain_id,
+        chain: RpcChain::new(&HashMap::from([(chain_id, url.clone())])).unwrap(),
+        provider: Provider::<Http>::try_from(url.as_str()).unwrap(),
+    })
+}
+
+/// Reads a variable of the environment of the devnet, which the devnet tests require.
+fn devnet_var<T: std::str::FromStr>(name: &str) -> T {
+    std::env::var(name)
+        .unwrap_or_else(|_| panic!("{} is not set", name))
+        .parse()
+        .unwrap_or_else(|_| panic!("{} can't be parsed", name))
+}
+
+/// Requires a DripsHub at `DEVNET_DRIPS_HUB`, deployed in `DEVNET_START_BLOCK`, in which account
+/// 0 of `DEVNET_DRIPS_USER` is configured.
+#[tokio::test]
+#[ignore]
+async fn the_drips_hub_is_read_from_a_devnet_in_ranges_of_blocks() {
+    let devnet = match devnet() {
+        Some(devnet) => devnet,
+        None => return,
+    };
+    let drips_hub: Address = devnet_var("DEVNET_DRIPS_HUB");
+    let start_block: u64 = devnet_var("DEVNET_START_BLOCK");
+    let user: Address = devnet_var("DEVNET_DRIPS_USER");
+    // the configuration of the account falls more than a range of blocks behind
+    let () = devnet
+        .provider
+        .request("anvil_mine", [U256::from(2 * MAX_BLOCKS)])
+        .await
+        .unwrap();
+
+    let drips = devnet
+        .chain
+        .drips_state(devnet.chain_id, drips_hub, start_block, user, U256::zero())
+        .await
+        .unwrap()
+        .expect("account 0 of DEVNET_DRIPS_USER is configured");
+    assert!(drips.update_time > 0);
+    assert!(!drips.receivers.is_empty());
+    assert_eq!(
+        devnet
+            .chain
+            .drips_state(
+                devnet.chain_id,
+                drips_hub,
+                start_block,
+                user,
+                U256::from(u64::MAX)
+            )
+            .await
+            .unwrap(),
+        None
+    );
+    // the Collected events of the receiver are read in ranges of blocks too
+    let (receiver, _) = drips.receivers[0];
+    devnet
+        .chain
+        .collected(devnet.chain_id, drips_hub, start_block, receiver)
+        .await
+        .unwrap();
+}
+
+/// Requires a smart contract wallet at `DEVNET_EIP1271_WALLET` whose `isValidSignature` accepts
+/// the signatures of its owner, whose private key is `DEVNET_EIP1271_OWNER_KEY`, like the wallets
+/// of `MockChain`.
+#[tokio::test]
+#[ignore]
+async fn eip1271_signatures_are_checked_by_a_wallet_on_a_devnet() {
+    let devnet = match devnet() {
+        Some(devnet) => devnet,
+        None => return,
+    };
+    let wallet: Address = devnet_var("DEVNET_EIP1271_WALLET");
+    let owner: LocalWallet = devnet_var("DEVNET_EIP1271_OWNER_KEY");
+    let message = "Sign in to Workstreams";
+    let hash = hash_message(message);
+    let signature = owner.sign_message(message).await.unwrap().to_vec();
+    assert!(devnet
+        .chain
+        .is_valid_signature(devnet.chain_id, wallet, hash, &signature)
+        .await
+        .unwrap());
+
+    // a signature by anyone else is rejected by the wallet
+    let stranger = LocalWallet::new(&mut rand::thread_rng());
+    let forged = stranger.sign_message(message).await.unwrap().to_vec();
+    assert!(!devnet
+        .chain
+        .is_valid_signature(devnet.chain_id, wallet, hash, &forged)
+        .await
+        .unwrap());
+
+    // an account without code isn't a smart contract wallet
+    assert!(!devnet
+        .chain
+        .is_valid_signature(devnet.chain_id, owner.address(), hash, &signature)
+        .await
+        .unwrap());
+}